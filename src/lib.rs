@@ -1,7 +1,9 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{Vector, Map};
-use near_sdk::{env, near_bindgen};
+use ed25519_dalek::{Signature, VerifyingKey};
+use near_sdk::collections::{Vector, Map, Set};
+use near_sdk::{env, near_bindgen, Balance, Gas, Promise, PromiseResult};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
@@ -12,273 +14,9106 @@ type Value = String;
 type AccountId = String;
 type ChannelId = String;
 type ChannelHash = Vec<u8>;
+type MessageKey = Vec<u8>;
+type Emoji = String;
+type Tag = String;
+type ReadMarkerKey = Vec<u8>;
 
 const CHAT_APP_ID: &[u8] = b"chat";
+const DELETED_MESSAGE_TEXT: &str = "";
+/// Reputation points lost for every reported message of an account removed by moderators.
+const REPUTATION_REPORT_PENALTY: u64 = 10;
+/// Reason recorded in the moderation log when a moderator removes a reported message.
+const REPORTED_MESSAGE_REMOVAL_REASON: &str = "Removed after reports";
+/// Channel IDs with this prefix are reserved for direct messages.
+const DIRECT_CHANNEL_PREFIX: &str = "dm-";
+/// Channel IDs with this prefix are reserved for group conversations.
+const GROUP_CHANNEL_PREFIX: &str = "group-";
+/// Maximum number of messages pruned when a new message is posted.
+const PRUNE_BATCH_SIZE: u64 = 10;
+/// Time in milliseconds an account is shown as typing after a `Typing` message.
+const TYPING_INDICATOR_MS: u64 = 10_000;
+/// Length of a UTC day in milliseconds.
+const MS_PER_DAY: u64 = 86_400_000;
+/// Length of an hour in milliseconds. Recent messages are counted in hourly buckets.
+const MS_PER_HOUR: u64 = 3_600_000;
+/// Number of the most active channels kept in the analytics.
+const NUM_TOP_CHANNELS: usize = 10;
+/// Number of public channels with the latest messages kept in the analytics for trending channels.
+const NUM_ACTIVE_CHANNELS: usize = 100;
+/// Maximum number of app store keys `get_by_prefix` looks at.
+const MAX_PREFIX_SCAN_KEYS: usize = 1000;
+/// Number of accounts kept in every leaderboard.
+const LEADERBOARD_SIZE: usize = 20;
+/// Maximum number of options in a poll.
+const MAX_POLL_OPTIONS: usize = 10;
+/// Maximum number of expiring app store values checked when a value is written.
+const APP_VALUE_SWEEP_BATCH_SIZE: u64 = 10;
+/// Price of one byte of contract storage in yoctoNEAR.
+const STORAGE_PRICE_PER_BYTE: Balance = 10_000_000_000_000_000_000;
+/// Gas attached to the call that checks whether an account passes the channel gate.
+const GAS_FOR_GATE_CHECK: Gas = 10_000_000_000_000;
+/// Gas attached to the callback that adds the account to the gated channel.
+const GAS_FOR_GATE_CALLBACK: Gas = 10_000_000_000_000;
+/// Gas attached to the `ft_balance_of` call that fetches the weight of a vote.
+const GAS_FOR_VOTE_WEIGHT: Gas = 10_000_000_000_000;
+/// Gas attached to the callback that records the weighted vote.
+const GAS_FOR_VOTE_CALLBACK: Gas = 20_000_000_000_000;
+/// Gas attached to every `on_chat_message` notification of a channel receiver.
+const GAS_FOR_MESSAGE_NOTIFICATION: Gas = 5_000_000_000_000;
+/// Gas attached to every `on_mention` notification of a mentioned account's receiver.
+const GAS_FOR_MENTION_NOTIFICATION: Gas = 5_000_000_000_000;
+/// Gas left for the rest of the call when attaching a notification. Notifications that would leave
+/// less are skipped, so a full batch of messages can't run out of gas.
+const GAS_RESERVED_AFTER_NOTIFICATIONS: Gas = 50_000_000_000_000;
+/// Gas attached to the `ft_transfer` call that withdraws tokens.
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+/// Gas attached to the callback that restores the token balance if the withdrawal fails.
+const GAS_FOR_WITHDRAW_CALLBACK: Gas = 10_000_000_000_000;
+const EVENT_STANDARD: &str = "metanear-chat";
+const EVENT_STANDARD_VERSION: &str = "1.0.0";
+/// Variants of `GetRequest` supported by `get`.
+const GET_REQUEST_KINDS: &[&str] = &[
+    "Status", "Channels", "ChannelsByTag", "TrendingChannels", "ChannelStatus", "ChannelMessages",
+    "ChannelMessagesReverse", "MessagesByIndices", "ThreadMessages", "MessageHistory", "Mentions",
+    "ModerationLog", "UnreadCounts", "PendingInvites", "ChannelMembers", "ExportChannel", "Bans",
+    "Profiles", "Presence", "AccountChannels", "ModerationQueue", "Reputation", "TopTipped",
+    "AppKeys", "EncryptionKeys", "CustomEmoji", "TypingAccounts", "HistoryCommitments",
+    "ChannelActivity", "Analytics", "TopPosters", "Batch",
+    "ChannelMessagesByTime", "ReactionsBy", "SigningNonce",
+];
+/// Variants of `IncomingMessage` supported by `post_message`.
+const INCOMING_MESSAGE_KINDS: &[&str] = &[
+    "ChatMessage", "ReplyMessage", "EditMessage", "DeleteMessage", "ModDelete", "BanAccount",
+    "UnbanAccount", "MuteAccount", "SetArchived", "SetAnnouncement", "SetSlowMode", "SetRetention",
+    "SetGate", "SetChannelMeta", "SetTags", "SetChannelKind", "AddMember", "RemoveMember", "Invite",
+    "AcceptInvite", "JoinChannel", "LeaveChannel", "DirectMessage", "CreateGroup", "Forward",
+    "Block", "Unblock", "Heartbeat", "SetProfile", "MarkRead", "React", "Unreact", "Report",
+    "ResolveReport", "SetReportThreshold", "SetMinReputation", "Tip", "SetSubscription", "SetEncryptionKey",
+    "CreatePoll", "Vote", "SetCustomEmoji", "Typing", "CrossPost", "SetReceivers",
+    "SetMentionReceiver", "SetSigningKey",
+];
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct MetanearChat {
-    channels: Map<ChannelHash, Channel>,
+    /// The account that administers the contract.
+    owner_id: AccountId,
+    /// While paused, all changes except for admin methods are rejected.
+    paused: bool,
+    /// IDs of all channels in the order they were created. Every channel is stored under its own
+    /// `channel_key`, so reading or saving a channel takes a single storage access.
+    channel_ids: Vector<ChannelId>,
+    /// Channels keyed by the sha256 hash of the channel ID in the first storage layout. They are
+    /// converted and moved to `channels` by `migrate_channels`.
+    legacy_channels: Map<ChannelHash, ChannelV1>,
     total_num_messages: u64,
+    /// Number of created group conversations, used to generate group channel IDs.
+    num_groups: u64,
+    /// Number of cross-posted messages, used to generate cross-post IDs.
+    num_crossposts: u64,
+    /// Accounts that reacted to a message, grouped by the emoji.
+    reactions: Map<MessageKey, Map<Emoji, Set<AccountId>>>,
+    config: Config,
+    /// Messages mentioning an account, in the order they were posted.
+    mentions: Map<AccountId, Vector<Mention>>,
+    /// The index of the last message read by an account in a channel.
+    read_markers: Map<ReadMarkerKey, u64>,
+    /// Pending invitations to private channels for every invited account.
+    invites: Map<AccountId, Map<ChannelHash, Invite>>,
+    /// Channels every account is a member of.
+    account_channels: Map<AccountId, Set<ChannelId>>,
+    /// Channels for every tag.
+    tag_channels: Map<Tag, Set<ChannelId>>,
+    profiles: Map<AccountId, Profile>,
+    /// Time in milliseconds of the last message from every account.
+    last_active: Map<AccountId, u64>,
+    /// Accounts every account blocked.
+    blocked_accounts: Map<AccountId, Set<AccountId>>,
+    /// NEP-145 storage balances that pay for the storage used by messages.
+    storage_balances: Map<AccountId, Balance>,
+    /// The current rate limit window of every account that posted.
+    post_windows: Map<AccountId, PostWindow>,
+    /// Anti-spam deposits held for recent messages.
+    message_deposits: Map<MessageKey, HeldDeposit>,
+    /// Lowercase words that messages can't contain.
+    banned_words: Set<String>,
+    /// Accounts banned by the owner from the whole contract.
+    global_bans: Map<AccountId, GlobalBan>,
+    reputations: Map<AccountId, Reputation>,
+    /// Accounts that posted the most messages to all channels.
+    top_posters: Leaderboard,
+    /// Total amount in yoctoNEAR tipped to every tipped message.
+    tips: Map<MessageKey, Balance>,
+    /// Fungible token balances of every account that received token tips, keyed by the token
+    /// contract.
+    token_balances: Map<AccountId, Map<AccountId, Balance>>,
+    /// Total amount in yoctoNEAR every account received as tips in all channels.
+    tips_received: Map<AccountId, Balance>,
+    /// Accounts that received the most tips in all channels.
+    top_tipped: Leaderboard,
+    /// Platform fees and forfeited deposits collected by the contract.
+    treasury: Treasury,
+    /// Keys stored in the generic app store of every app.
+    app_keys: Map<AppId, Set<Key>>,
+    /// Expiration of app store values written with a TTL, keyed by the storage key of the value.
+    app_value_expirations: Map<Vec<u8>, AppValueExpiration>,
+    /// Apps administered by their own accounts instead of only the contract owner.
+    apps: Map<AppId, App>,
+    /// Messages posted to apps other than the chat app, in the order they were posted.
+    app_inboxes: Map<AppId, Vector<InboxMessage>>,
+    /// Contracts that can post messages on behalf of other accounts.
+    trusted_proxies: Set<AccountId>,
+    /// Deployments of this contract that can push messages posted to them into mirrored channels.
+    trusted_peers: Set<AccountId>,
+    /// Base64-encoded X25519 public keys used for end-to-end encrypted messages.
+    encryption_keys: Map<AccountId, String>,
+    /// Base64-encoded ed25519 public keys that sign the messages relayed with `post_signed_message`.
+    signing_keys: Map<AccountId, String>,
+    /// The nonce of the last signed message of every account. A signed message is only accepted with
+    /// a greater nonce, so it can't be replayed.
+    signing_nonces: Map<AccountId, u64>,
+    /// Contracts notified with `on_mention` of the messages mentioning every account.
+    mention_receivers: Map<AccountId, AccountId>,
+    /// Tallies of every poll that has votes.
+    poll_votes: Map<MessageKey, PollTally>,
+    /// The option chosen by every account that voted in a poll.
+    poll_voters: Map<MessageKey, Map<AccountId, u64>>,
+    /// IPFS content identifiers of the custom emoji images of every channel by emoji name.
+    custom_emoji: Map<ChannelId, BTreeMap<String, String>>,
+    /// Accounts typing in every channel. Expired entries are removed on the next `Typing` message.
+    typing: Map<ChannelId, Vec<TypingIndicator>>,
+    /// Emoji every account reacted with to the messages of a channel, keyed by the account and the
+    /// channel hash.
+    account_reactions: Map<Vec<u8>, BTreeMap<u64, Vec<Emoji>>>,
+    analytics: Analytics,
+    /// Channels that received messages during the current call. They are pruned after the storage of
+    /// the call is charged.
+    #[borsh_skip]
+    posted_channel_ids: Vec<ChannelId>,
+}
+
+/// A text message signed by its sender and submitted by a relayer with `post_signed_message`.
+#[derive(Deserialize)]
+pub struct SignedPayload {
+    /// The account of the contract the message is signed for, so it can't be relayed to another deployment.
+    contract_id: AccountId,
+    sender_id: AccountId,
+    channel_id: ChannelId,
+    text: String,
+    /// Should be greater than the nonce of the last signed message of the sender.
+    nonce: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct Invite {
+    channel_id: ChannelId,
+    inviter_id: AccountId,
+    /// Time in milliseconds when the invite was sent.
+    time: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Mention {
+    channel_id: ChannelId,
+    message_index: u64,
+}
+
+/// The contract state in the first storage layout.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct MetanearChatV1 {
+    channels: Map<ChannelHash, ChannelV1>,
+    total_num_messages: u64,
+}
+
+/// A channel in the first storage layout.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ChannelV1 {
+    channel_id: ChannelId,
+    messages: Vector<MessageV1>,
+}
+
+/// A message in the first storage layout.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct MessageV1 {
+    /// Time in milliseconds.
+    time: u64,
+    sender_id: AccountId,
+    text: String,
+}
+
+/// The contract state in any of the known storage layouts.
+pub enum VersionedState {
+    V1(MetanearChatV1),
+    Current(Box<MetanearChat>),
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct Config {
+    /// Maximum length of the message text in bytes.
+    max_message_len: u64,
+    max_num_attachments: u64,
+    /// Maximum declared size of a single attachment in bytes.
+    max_attachment_size: u64,
+    /// Maximum number of mentions recorded for a single message. Extra mentions are ignored.
+    max_num_mentions: u64,
+    /// Maximum number of messages in a single `post_messages` call.
+    max_batch_size: u64,
+    /// Maximum number of members in a group conversation.
+    max_group_size: u64,
+    /// Maximum number of tags attached to a channel.
+    max_num_tags: u64,
+    min_app_id_len: u64,
+    max_app_id_len: u64,
+    max_channel_id_len: u64,
+    /// Maximum number of messages an account can post across all channels within `post_window_ms`.
+    max_posts_per_window: u64,
+    post_window_ms: u64,
+    /// Maximum number of items returned by a single paginated request. Larger limits are reduced to
+    /// it.
+    max_page_size: u64,
+    /// Number of items returned by a paginated request with a zero limit, up to `max_page_size`.
+    default_page_size: u64,
+    /// Deposit in yoctoNEAR held for every posted message. Zero disables the deposit.
+    message_deposit: String,
+    /// Time in milliseconds before the message deposit can be released.
+    message_deposit_cooldown_ms: u64,
+    /// Share of every tip in basis points that goes to the treasury.
+    tip_fee_bps: u64,
+    /// Share of every channel subscription payment in basis points that goes to the treasury.
+    subscription_fee_bps: u64,
+    /// Maximum number of custom emoji registered in a channel.
+    max_custom_emoji: u64,
+    /// Maximum number of channels a message can be cross-posted to.
+    max_crosspost_channels: u64,
+    /// Maximum number of contracts notified of the messages of a channel.
+    max_channel_receivers: u64,
+    /// Maximum number of key hints attached to an encrypted message.
+    max_encryption_key_ids: u64,
+}
+
+/// A stored channel in any of the known layouts. A new layout of `Channel` is added as a new
+/// variant. Channels in older layouts are upgraded when they are read and stored in the current
+/// layout the next time they are saved.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedChannel {
+    V1(Channel),
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Channel {
     channel_id: ChannelId,
-    messages: Vector<Message>,
+    /// The account that created the channel and can manage it.
+    owner_id: AccountId,
+    messages: Vector<VersionedMessage>,
+    /// Indices of the replies for every message that has at least one reply.
+    threads: Map<u64, Vector<u64>>,
+    /// Prior revisions for every message that was edited.
+    revisions: Map<u64, Vector<Revision>>,
+    /// Accounts that can remove any message in the channel.
+    moderators: Set<AccountId>,
+    moderation_log: Vector<ModerationLogEntry>,
+    /// Archived channels are read-only.
+    archived: bool,
+    /// Only the owner and moderators can post to announcement channels.
+    announcement: bool,
+    /// Minimum time in milliseconds between two messages from the same sender. Zero disables slow
+    /// mode.
+    slow_mode_ms: u64,
+    /// Time of the last message from every sender, recorded while slow mode is enabled.
+    last_posts: Map<AccountId, u64>,
+    retention: RetentionPolicy,
+    /// Accounts must pass the gate with `join_gated_channel` before they can post to the channel.
+    gate: Option<ChannelGate>,
+    /// Accounts banned from posting to the channel.
+    bans: Map<AccountId, Ban>,
+    /// Time in milliseconds until which every muted account can't post to the channel. Expired mutes
+    /// are removed when the account posts again.
+    mutes: Map<AccountId, u64>,
+    /// Open reports for every reported message, in the order the messages were first reported.
+    reports: Map<u64, Vec<Report>>,
+    /// Number of reports that hide a message until a moderator resolves them. Zero disables hiding.
+    report_threshold: u64,
+    /// Minimum reputation score required to post to the channel. Moderators are exempt.
+    min_reputation: u64,
+    /// Total amount in yoctoNEAR every account received as tips in the channel.
+    tips_received: Map<AccountId, Balance>,
+    /// Accounts that received the most tips in the channel.
+    top_tipped: Leaderboard,
+    /// Accounts have to pay to join a channel with a subscription. Their membership gives access to
+    /// a private channel only until the subscription expires.
+    subscription: Option<Subscription>,
+    /// Time in milliseconds when the subscription of every subscribed account expires.
+    subscriptions: Map<AccountId, u64>,
+    /// Number of the oldest messages pruned by the retention policy. Pruned messages keep their
+    /// indices but their content is replaced with a tombstone.
+    num_pruned_messages: u64,
+    /// A Merkle root over every batch of pruned messages.
+    history_commitments: Vector<HistoryCommitment>,
+    /// The hash chain of the messages. The link of every message is the sha256 of the link of the
+    /// previous message, if any, followed by the Borsh-serialized message as it was posted.
+    chain_links: Vector<Vec<u8>>,
+    /// Number of messages posted on every UTC day, counted from the Unix epoch.
+    daily_activity: Map<u64, u64>,
+    /// Number of messages every account posted to the channel.
+    num_messages_by_sender: Map<AccountId, u64>,
+    /// Accounts that posted the most messages to the channel.
+    top_posters: Leaderboard,
+    topic: String,
+    description: String,
+    /// Tags used to discover the channel.
+    tags: Vec<Tag>,
+    /// Contracts notified with `on_chat_message` of every message posted to the channel.
+    receivers: Vec<AccountId>,
+    /// Time in milliseconds when the channel was created.
+    created_at: u64,
+    /// Time in milliseconds of the last posted message.
+    last_message_time: u64,
+    kind: ChannelKind,
+    /// Accounts that joined the channel with the time in milliseconds when they joined. Only members
+    /// can access a private channel.
+    members: Map<AccountId, u64>,
+}
+
+/// Limits on the messages kept in a channel. Older messages are pruned once either limit is exceeded.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default, Clone)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    max_messages: Option<u64>,
+    /// Maximum age of a message in milliseconds.
+    #[serde(default)]
+    max_age_ms: Option<u64>,
+}
+
+/// A requirement an account has to meet to join the channel, verified with a cross-contract call.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub enum ChannelGate {
+    /// The account must hold at least `min_balance` of the NEP-141 fungible token.
+    FungibleToken {
+        token_id: AccountId,
+        min_balance: String,
+    },
+    /// The account must own at least one token of the NEP-171 collection.
+    NonFungibleToken {
+        contract_id: AccountId,
+    },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ChannelKind {
+    Public,
+    /// Only members can read and post messages.
+    Private,
+    /// A private channel between two accounts.
+    Direct,
+    /// A private conversation with a generated ID and members managed by the creator.
+    Group,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct Subscription {
+    /// Price in yoctoNEAR of one subscription period.
+    price: String,
+    period_ms: u64,
+}
+
+/// Public information about an account shown by chat clients.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct Profile {
+    display_name: String,
+    /// An empty string if the account has no avatar.
+    avatar_url: String,
+    bio: String,
+}
+
+/// A channel created when the contract is initialized.
+#[derive(Deserialize)]
+pub struct BootstrapChannel {
+    channel_id: ChannelId,
+    #[serde(default)]
+    topic: String,
+}
+
+/// Counts the messages an account posted since the window started.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PostWindow {
+    /// Time in milliseconds when the window started.
+    start: u64,
+    num_posts: u64,
+}
+
+/// A deposit taken from the storage balance of the sender when posting a message. It's returned to
+/// the storage balance after the cool-down, unless a moderator removes the message first.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct HeldDeposit {
+    account_id: AccountId,
+    amount: Balance,
+    /// Time in milliseconds when the deposit can be released.
+    release_time: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct Ban {
+    moderator_id: AccountId,
+    reason: String,
+    /// Time in milliseconds when the account was banned.
+    time: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct Report {
+    reporter_id: AccountId,
+    reason: String,
+    /// Time in milliseconds when the message was reported.
+    time: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Default)]
+pub struct Reputation {
+    num_messages: u64,
+    /// Number of reactions from other accounts to the messages of the account.
+    num_reactions_received: u64,
+    /// Number of reported messages of the account that moderators removed. Dismissed reports don't
+    /// count.
+    num_reports: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct GlobalBan {
+    reason: String,
+    /// Time in milliseconds when the account was banned.
+    time: u64,
+    /// Whether the messages of the account are redacted in views.
+    hide_messages: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct ModerationLogEntry {
+    /// Time in milliseconds of the moderation action.
+    time: u64,
+    moderator_id: AccountId,
+    message_index: u64,
+    reason: String,
+}
+
+/// A Merkle root over a range of pruned messages, computed before their content was removed.
+/// Leaves are the sha256 hashes of the Borsh-serialized messages in index order. Every parent is
+/// the sha256 of its two children concatenated, and an odd last node is carried up unchanged.
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct HistoryCommitment {
+    /// The index of the first pruned message.
+    from_index: u64,
+    /// The index after the last pruned message.
+    to_index: u64,
+    /// Hex-encoded Merkle root.
+    root: String,
+    /// Time in milliseconds when the messages were pruned.
+    time: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct Message {
+    /// Time in milliseconds.
+    time: u64,
+    /// The account Id of the message sender.
+    sender_id: AccountId,
+    /// The content of the message.
+    body: MessageBody,
+    /// Files attached to the message.
+    attachments: Vec<Attachment>,
+    /// The index of the message in the same channel this message replies to.
+    reply_to: Option<u64>,
+    /// The original message if this message was forwarded from another channel.
+    forwarded_from: Option<ForwardedFrom>,
+    /// Time in milliseconds of the last edit, if the message was edited.
+    edited_at: Option<u64>,
+    /// Whether the message was deleted by the sender. The text of a deleted message is replaced
+    /// with a tombstone.
+    deleted: bool,
+    /// Whether the message was hidden after being reported. Hidden messages are redacted in views
+    /// until a moderator resolves the reports.
+    hidden: bool,
+    /// Shared by the copies of a message cross-posted to several channels.
+    crosspost_id: Option<u64>,
+    /// The original message if this message was pushed by a peer deployment of this contract.
+    bridged_from: Option<BridgedFrom>,
+    /// The account that paid for the storage of the message. Messages converted from the first
+    /// storage layout were paid for by the contract and have no payer.
+    storage_payer_id: Option<AccountId>,
+}
+
+/// A stored message in any of the known layouts. A new layout of `Message` is added as a new
+/// variant, and messages in older layouts are upgraded when they are read.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedMessage {
+    V1(Message),
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct Revision {
+    /// Time in milliseconds when this revision of the text was posted.
+    time: u64,
+    /// The content of the message at this revision.
+    body: MessageBody,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct BridgedFrom {
+    /// The peer contract the message was posted to.
+    contract_id: AccountId,
+    channel_id: ChannelId,
+    message_index: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct ForwardedFrom {
+    channel_id: ChannelId,
+    message_index: u64,
+    /// The account Id of the original message sender.
+    sender_id: AccountId,
+    /// Time in milliseconds of the original message.
+    time: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    /// IPFS content identifier of the file.
+    cid: String,
+    mime: String,
+    /// Size of the file in bytes.
+    size: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub enum MessageBody {
+    Text {
+        text: String,
+    },
+    Image {
+        /// IPFS content identifier of the image.
+        cid: String,
+        mime: String,
+    },
+    Link {
+        url: String,
+        title: String,
+    },
+    /// A message posted by the contract itself.
+    System {
+        text: String,
+    },
+    /// A poll created with `CreatePoll`. Accounts vote with `Vote` until `closes_at`.
+    Poll {
+        question: String,
+        options: Vec<String>,
+        /// Time in milliseconds after which votes are rejected.
+        closes_at: u64,
+        /// The fungible token contract whose balance weights the votes, if the poll is weighted.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        weight_token_id: Option<AccountId>,
+    },
+    /// A message encrypted by the client. The contract stores it without looking at the content.
+    Encrypted {
+        ciphertext_b64: String,
+        /// Hints that tell recipients which of their keys decrypts the message.
+        key_ids: Vec<String>,
+    },
+}
+
+#[derive(Deserialize)]
+pub enum GetRequest {
+    /// Evaluates every request and returns the array of their responses.
+    Batch(Vec<GetRequest>),
+    Status {},
+    /// Returns channels in the order they were created.
+    Channels {
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        skip_archived: bool,
+    },
+    /// Returns channels with the given tag in the order they were tagged.
+    ChannelsByTag {
+        tag: Tag,
+        from_index: u64,
+        limit: u64,
+    },
+    /// Returns public channels with the most messages posted within the last `window_ms`
+    /// milliseconds, most active first. Only the 100 channels with the latest messages are
+    /// considered.
+    TrendingChannels {
+        limit: u64,
+        window_ms: u64,
+    },
+    /// Returns accounts that received the most NEAR tips in the channel, or in all channels without
+    /// a channel ID. Only the top 20 accounts are kept.
+    TopTipped {
+        #[serde(default)]
+        channel_id: Option<ChannelId>,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns accounts that posted the most messages to the channel, or to all channels without a
+    /// channel ID. Only the top 20 accounts are kept.
+    TopPosters {
+        #[serde(default)]
+        channel_id: Option<ChannelId>,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    ChannelStatus {
+        channel_id: ChannelId,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    ChannelMessages {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        /// The account reading a private channel.
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+        /// Whether to return profiles of the message senders.
+        #[serde(default)]
+        include_profiles: bool,
+        /// Messages from accounts blocked by the viewer are redacted.
+        #[serde(default)]
+        viewer_id: Option<AccountId>,
+    },
+    /// Returns channel messages from the newest to the oldest, skipping `from_end` newest messages.
+    ChannelMessagesReverse {
+        channel_id: ChannelId,
+        from_end: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+        #[serde(default)]
+        viewer_id: Option<AccountId>,
+    },
+    /// Returns channel messages posted from `from_time` inclusive to `to_time` exclusive, in
+    /// milliseconds, from the oldest to the newest.
+    ChannelMessagesByTime {
+        channel_id: ChannelId,
+        from_time: u64,
+        to_time: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+        #[serde(default)]
+        viewer_id: Option<AccountId>,
+    },
+    /// Returns the messages with the given indices. Indices that don't exist are skipped.
+    MessagesByIndices {
+        channel_id: ChannelId,
+        indices: Vec<u64>,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+        #[serde(default)]
+        viewer_id: Option<AccountId>,
+    },
+    ThreadMessages {
+        channel_id: ChannelId,
+        root_index: u64,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+        #[serde(default)]
+        viewer_id: Option<AccountId>,
+    },
+    MessageHistory {
+        channel_id: ChannelId,
+        message_index: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    Mentions {
+        account_id: AccountId,
+        from_index: u64,
+        limit: u64,
+    },
+    ModerationLog {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns Merkle roots over the pruned messages of the channel in the order they were pruned.
+    HistoryCommitments {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns reported messages that wait for a moderator, in the order they were first reported.
+    ModerationQueue {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    UnreadCounts {
+        account_id: AccountId,
+        channel_ids: Vec<ChannelId>,
+    },
+    PendingInvites {
+        account_id: AccountId,
+    },
+    /// Returns channel members in the order they joined.
+    ChannelMembers {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns up to `limit` messages starting at `from_index` in the order they were posted, without
+    /// reactions. Messages keep their indices forever, so a mirror can fetch the whole history by
+    /// starting at zero and repeating the request with `next_index` until it's `null`.
+    ExportChannel {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns accounts banned from the channel.
+    Bans {
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns profiles of the given accounts. Accounts without a profile are omitted.
+    Profiles {
+        account_ids: Vec<AccountId>,
+    },
+    /// Returns when the given accounts were last active. Accounts that were never active are omitted.
+    Presence {
+        account_ids: Vec<AccountId>,
+    },
+    /// Returns encryption keys of the given accounts. Accounts without a key are omitted.
+    EncryptionKeys {
+        account_ids: Vec<AccountId>,
+    },
+    /// Returns the nonce of the last signed message of the account. The next signed message should
+    /// use a greater nonce.
+    SigningNonce {
+        account_id: AccountId,
+    },
+    /// Returns the custom emoji registered in the channel.
+    CustomEmoji {
+        channel_id: ChannelId,
+    },
+    /// Returns the messages of the channel the account reacted to with its emoji, by message index.
+    ReactionsBy {
+        account_id: AccountId,
+        channel_id: ChannelId,
+    },
+    /// Returns accounts currently typing in the channel.
+    TypingAccounts {
+        channel_id: ChannelId,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns the number of messages posted to the channel on every UTC day in the inclusive
+    /// range that had messages. Days are counted from the Unix epoch and at most `max_page_size`
+    /// days are returned.
+    ChannelActivity {
+        channel_id: ChannelId,
+        from_day: u64,
+        to_day: u64,
+        #[serde(default)]
+        reader_id: Option<AccountId>,
+    },
+    /// Returns usage statistics of the whole contract.
+    Analytics {},
+    Reputation {
+        account_id: AccountId,
+    },
+    /// Returns channels the account is a member of.
+    AccountChannels {
+        account_id: AccountId,
+        from_index: u64,
+        limit: u64,
+    },
+    /// Returns keys stored in the generic app store of the app.
+    AppKeys {
+        app_id: AppId,
+        from_index: u64,
+        limit: u64,
+    },
+}
+
+#[derive(BorshSerialize, Serialize)]
+pub struct StatusResponse {
+    num_channels: u64,
+    total_num_messages: u64,
+}
+
+#[derive(Serialize)]
+pub struct ChannelInfo {
+    channel_id: ChannelId,
+    kind: ChannelKind,
+    num_members: u64,
+    num_messages: u64,
+    archived: bool,
+}
+
+#[derive(Serialize)]
+pub struct ChannelsResponse {
+    num_channels: u64,
+    channels: Vec<ChannelInfo>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+pub struct InboxMessage {
+    sender_id: AccountId,
+    /// The message as it was posted. It isn't parsed by this contract.
+    message: String,
+    /// Time in milliseconds when the message was posted.
+    time: u64,
+}
+
+#[derive(Serialize)]
+pub struct AppInboxResponse {
+    num_messages: u64,
+    messages: Vec<InboxMessage>,
+}
+
+#[derive(Serialize)]
+pub struct AppKeysResponse {
+    num_keys: u64,
+    keys: Vec<Key>,
+}
+
+#[derive(Serialize)]
+pub struct TrendingChannel {
+    channel_id: ChannelId,
+    /// Number of messages posted within the requested window.
+    num_recent_messages: u64,
+    last_message_time: u64,
+}
+
+#[derive(Serialize)]
+pub struct TrendingChannelsResponse {
+    channels: Vec<TrendingChannel>,
+}
+
+#[derive(Serialize)]
+pub struct TippedAccount {
+    account_id: AccountId,
+    /// Total amount in yoctoNEAR received as tips.
+    amount: String,
+}
+
+#[derive(Serialize)]
+pub struct TopTippedResponse {
+    accounts: Vec<TippedAccount>,
+}
+
+#[derive(Serialize)]
+pub struct Poster {
+    account_id: AccountId,
+    num_messages: u64,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize)]
-pub struct Message {
-    /// Time in nanoseconds.
-    time: u64,
-    /// The account Id of the message sender.
-    sender_id: AccountId,
-    /// The content of the message.
-    text: String,
-}
+#[derive(Serialize)]
+pub struct TopPostersResponse {
+    accounts: Vec<Poster>,
+}
+
+#[derive(BorshSerialize, Serialize)]
+pub struct ChannelStatusResponse {
+    owner_id: AccountId,
+    kind: ChannelKind,
+    announcement: bool,
+    num_members: u64,
+    num_messages: u64,
+    num_pruned_messages: u64,
+    retention: RetentionPolicy,
+    gate: Option<ChannelGate>,
+    subscription: Option<Subscription>,
+    topic: String,
+    description: String,
+    tags: Vec<Tag>,
+    receivers: Vec<AccountId>,
+    /// Hex-encoded chain link of the last message, if the channel has messages.
+    chain_head: Option<String>,
+    created_at: u64,
+}
+
+#[derive(BorshSerialize, Serialize)]
+pub struct ReactionCount {
+    emoji: Emoji,
+    count: u64,
+}
+
+#[derive(BorshSerialize, Serialize)]
+pub struct MessageView {
+    #[serde(flatten)]
+    message: Message,
+    reactions: Vec<ReactionCount>,
+    /// Total amount in yoctoNEAR tipped to the message, if it was tipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tips: Option<String>,
+    /// Number of votes for every option, if the message is a poll.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poll_votes: Option<Vec<u64>>,
+    /// Total token balance of the voters for every option, if the message is a weighted poll.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poll_weights: Option<Vec<String>>,
+    /// Hex-encoded link of the message in the hash chain of the channel.
+    chain_link: String,
+    /// Number of replies to the message, if it has replies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_count: Option<u64>,
+    /// Time in milliseconds of the latest reply, if the message has replies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_reply_time: Option<u64>,
+    /// Whether the content was hidden because the viewer blocked the sender, the sender is banned
+    /// from the contract or the message awaits moderator review.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    redacted: bool,
+}
+
+#[derive(BorshSerialize, Serialize)]
+pub struct ChannelMessagesResponse {
+    messages: Vec<MessageView>,
+    /// Number of messages in the channel.
+    total: u64,
+    /// Whether the channel has messages after the returned page.
+    has_more: bool,
+    /// Profiles of the message senders, if requested. Senders without a profile are omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profiles: Option<BTreeMap<AccountId, Profile>>,
+}
+
+#[derive(Serialize)]
+pub struct IndexedMessage {
+    /// The index of the message in the channel.
+    index: u64,
+    #[serde(flatten)]
+    message: MessageView,
+}
+
+#[derive(Serialize)]
+pub struct ChannelMessagesReverseResponse {
+    num_messages: u64,
+    messages: Vec<IndexedMessage>,
+}
+
+#[derive(Serialize)]
+pub struct ChannelMessagesByTimeResponse {
+    messages: Vec<IndexedMessage>,
+}
+
+#[derive(Serialize)]
+pub struct MessagesByIndicesResponse {
+    messages: Vec<IndexedMessage>,
+}
+
+#[derive(Serialize)]
+pub struct ThreadMessagesResponse {
+    num_replies: u64,
+    messages: Vec<IndexedMessage>,
+}
+
+
+#[derive(Serialize)]
+pub struct MentionedMessage {
+    channel_id: ChannelId,
+    /// The index of the message in the channel.
+    index: u64,
+    #[serde(flatten)]
+    message: MessageView,
+}
+
+#[derive(Serialize)]
+pub struct MentionsResponse {
+    num_mentions: u64,
+    messages: Vec<MentionedMessage>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryCommitmentsResponse {
+    num_commitments: u64,
+    commitments: Vec<HistoryCommitment>,
+}
+
+#[derive(Serialize)]
+pub struct ModerationLogResponse {
+    num_entries: u64,
+    entries: Vec<ModerationLogEntry>,
+}
+
+#[derive(Serialize)]
+pub struct ReportedMessage {
+    message_index: u64,
+    hidden: bool,
+    reports: Vec<Report>,
+}
+
+#[derive(Serialize)]
+pub struct ModerationQueueResponse {
+    num_reported_messages: u64,
+    messages: Vec<ReportedMessage>,
+}
+
+#[derive(Serialize)]
+pub struct UnreadCount {
+    channel_id: ChannelId,
+    /// The index of the last message read by the account, if any.
+    last_read_index: Option<u64>,
+    num_unread: u64,
+}
+
+#[derive(Serialize)]
+pub struct UnreadCountsResponse {
+    unread_counts: Vec<UnreadCount>,
+}
+
+#[derive(Serialize)]
+pub struct ChannelMember {
+    account_id: AccountId,
+    /// Time in milliseconds when the account joined the channel.
+    joined_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct ChannelMembersResponse {
+    num_members: u64,
+    members: Vec<ChannelMember>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedMessage {
+    index: u64,
+    #[serde(flatten)]
+    message: MessageView,
+}
+
+#[derive(Serialize)]
+pub struct ExportChannelResponse {
+    num_messages: u64,
+    messages: Vec<ExportedMessage>,
+    /// The index to continue the export from or `null` if there are no more messages.
+    next_index: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ProfilesResponse {
+    profiles: BTreeMap<AccountId, Profile>,
+}
+
+#[derive(Serialize)]
+pub struct PresenceResponse {
+    last_active: BTreeMap<AccountId, u64>,
+}
+
+#[derive(Serialize)]
+pub struct EncryptionKeysResponse {
+    encryption_keys: BTreeMap<AccountId, String>,
+}
+
+#[derive(Serialize)]
+pub struct SigningNonceResponse {
+    nonce: u64,
+}
+
+#[derive(Serialize)]
+pub struct TypingAccountsResponse {
+    account_ids: Vec<AccountId>,
+}
+
+#[derive(Serialize)]
+pub struct DailyActivity {
+    day: u64,
+    num_messages: u64,
+}
+
+#[derive(Serialize)]
+pub struct ChannelActivityResponse {
+    days: Vec<DailyActivity>,
+}
+
+#[derive(Serialize)]
+pub struct AnalyticsResponse {
+    top_channels: Vec<ChannelMessageCount>,
+    num_senders: u64,
+    /// Number of messages posted in the last 24 hours, counted in whole hours.
+    num_recent_messages: u64,
+    /// Storage used by the contract in bytes.
+    storage_usage: u64,
+}
+
+#[derive(Serialize)]
+pub struct ReactionsByResponse {
+    /// Emoji of the account by message index.
+    reactions: BTreeMap<u64, Vec<Emoji>>,
+}
+
+#[derive(Serialize)]
+pub struct CustomEmojiResponse {
+    /// IPFS content identifiers of the images by emoji name.
+    custom_emoji: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+pub struct ReputationResponse {
+    #[serde(flatten)]
+    reputation: Reputation,
+    score: u64,
+}
+
+#[derive(Serialize)]
+pub struct BannedAccount {
+    account_id: AccountId,
+    #[serde(flatten)]
+    ban: Ban,
+}
+
+#[derive(Serialize)]
+pub struct BansResponse {
+    num_bans: u64,
+    bans: Vec<BannedAccount>,
+}
+
+#[derive(Serialize)]
+pub struct PendingInvitesResponse {
+    invites: Vec<Invite>,
+}
+
+#[derive(Serialize)]
+pub struct MessageHistoryResponse {
+    /// Prior revisions of the message from the oldest to the newest. The current text is not
+    /// included.
+    revisions: Vec<Revision>,
+}
+
+/// Storage freed by replacing a message with a tombstone. It's returned to the storage balance of
+/// the account that paid for it.
+pub struct FreedStorage {
+    account_id: AccountId,
+    num_bytes: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct App {
+    owner_id: AccountId,
+    /// Accounts the app owner allowed to write to the app store.
+    writers: Set<AccountId>,
+    metadata: AppMetadata,
+}
+
+/// Public information about an app shown in the app registry.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+pub struct AppMetadata {
+    description: String,
+    /// An empty string if the app has no icon.
+    icon_url: String,
+    /// Version of the layout of the keys and values the app stores.
+    schema_version: u32,
+}
+
+#[derive(Serialize)]
+pub struct AppView {
+    app_id: AppId,
+    owner_id: AccountId,
+    metadata: AppMetadata,
+    num_keys: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TypingIndicator {
+    account_id: AccountId,
+    /// Time in milliseconds after which the account is no longer shown as typing.
+    expires_at: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PollTally {
+    /// Number of votes for every option.
+    votes: Vec<u64>,
+    /// Total token balance of the voters for every option. Stays zero unless the poll is weighted.
+    weights: Vec<Balance>,
+}
+
+impl PollTally {
+    pub fn new(num_options: usize) -> Self {
+        Self { votes: vec![0; num_options], weights: vec![0; num_options] }
+    }
+}
+
+impl Leaderboard {
+    /// Records the new total of the account.
+    pub fn record(&mut self, account_id: &AccountId, total: u128) {
+        // Totals only grow, so an account can only enter the list by passing its last entry.
+        self.entries.retain(|(entry_id, _)| entry_id != account_id);
+        self.entries.push((account_id.clone(), total));
+        self.entries.sort_by_key(|(_, total)| std::cmp::Reverse(*total));
+        self.entries.truncate(LEADERBOARD_SIZE);
+    }
+
+    /// Returns up to `limit` accounts with the highest totals.
+    pub fn top(&self, limit: u64) -> impl Iterator<Item = &(AccountId, u128)> {
+        self.entries.iter().take(limit as usize)
+    }
+}
+
+impl Analytics {
+    /// Records a message posted to any channel.
+    pub fn record_message(&mut self, new_sender: bool, time: u64) {
+        if new_sender {
+            self.num_senders += 1;
+        }
+        let hour = time / MS_PER_HOUR;
+        self.recent_activity.retain(|activity| activity.hour + 24 > hour);
+        match self.recent_activity.last_mut() {
+            Some(activity) if activity.hour == hour => activity.num_messages += 1,
+            _ => self.recent_activity.push(HourlyActivity { hour, num_messages: 1 }),
+        }
+    }
+
+    /// Records a message posted to the public channel, which now has `num_messages` messages.
+    pub fn record_top_channel(&mut self, channel_id: &ChannelId, num_messages: u64) {
+        // Message counts only grow, so a channel can only enter the list by passing its last entry.
+        self.top_channels.retain(|count| &count.channel_id != channel_id);
+        self.top_channels.push(ChannelMessageCount { channel_id: channel_id.clone(), num_messages });
+        self.top_channels.sort_by_key(|count| std::cmp::Reverse(count.num_messages));
+        self.top_channels.truncate(NUM_TOP_CHANNELS);
+    }
+
+    /// Moves the public channel that received a message to the front of the active channels.
+    pub fn record_active_channel(&mut self, channel_id: &ChannelId) {
+        self.active_channel_ids.retain(|active_id| active_id != channel_id);
+        self.active_channel_ids.insert(0, channel_id.clone());
+        self.active_channel_ids.truncate(NUM_ACTIVE_CHANNELS);
+    }
+
+    /// Removes the channel from the top and active channels once it's no longer public.
+    pub fn forget_channel(&mut self, channel_id: &ChannelId) {
+        self.top_channels.retain(|count| &count.channel_id != channel_id);
+        self.active_channel_ids.retain(|active_id| active_id != channel_id);
+    }
+
+    /// Returns the number of messages posted in the 24 hours before the time.
+    pub fn num_recent_messages(&self, time: u64) -> u64 {
+        let hour = time / MS_PER_HOUR;
+        self.recent_activity
+            .iter()
+            .filter(|activity| activity.hour + 24 > hour)
+            .map(|activity| activity.num_messages)
+            .sum()
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AppValueExpiration {
+    app_id: AppId,
+    key: Key,
+    /// Time in milliseconds after which the value is treated as absent.
+    expires_at: u64,
+}
+
+/// Amounts in yoctoNEAR.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Treasury {
+    balance: Balance,
+    total_collected: Balance,
+    total_withdrawn: Balance,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+pub struct ChannelMessageCount {
+    channel_id: ChannelId,
+    num_messages: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct HourlyActivity {
+    /// Hours since the Unix epoch.
+    hour: u64,
+    num_messages: u64,
+}
+
+/// Accounts with the highest totals, highest first.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Leaderboard {
+    entries: Vec<(AccountId, u128)>,
+}
+
+/// Usage statistics updated on every posted message.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Analytics {
+    /// Public channels with the most messages, most active first.
+    top_channels: Vec<ChannelMessageCount>,
+    /// Number of accounts that posted at least one message.
+    num_senders: u64,
+    /// Number of messages posted in every hour of the last 24 hours that had messages.
+    recent_activity: Vec<HourlyActivity>,
+    /// Public channels with the latest messages, most recent first.
+    active_channel_ids: Vec<ChannelId>,
+}
+
+#[derive(Serialize)]
+pub struct TreasuryView {
+    balance: String,
+    total_collected: String,
+    total_withdrawn: String,
+}
+
+/// NEP-145 storage balance in yoctoNEAR. Balances are strings since JSON numbers can't hold them.
+#[derive(Serialize)]
+pub struct StorageBalance {
+    total: String,
+    available: String,
+}
+
+impl StorageBalance {
+    fn new(balance: Balance) -> Self {
+        Self { total: balance.to_string(), available: balance.to_string() }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ContractMetadata<'a> {
+    /// Version of the contract code.
+    version: &'static str,
+    /// Standard and version of the emitted events.
+    standard: &'static str,
+    standard_version: &'static str,
+    get_requests: &'static [&'static str],
+    incoming_messages: &'static [&'static str],
+    config: &'a Config,
+}
+
+/// The `msg` of a fungible token transfer to the contract.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTransferMessage {
+    /// Credits the tokens to the author of the message.
+    Tip {
+        channel_id: ChannelId,
+        message_index: u64,
+    },
+}
+
+#[derive(Serialize)]
+pub struct StorageBalanceBounds {
+    min: String,
+    max: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PostResult {
+    /// The channel the message was posted to. Empty for messages that don't target a channel.
+    channel_id: ChannelId,
+    message_index: u64,
+    /// Time in milliseconds when the message was processed.
+    time: u64,
+}
+
+/// A structured event in NEP-297 format, logged with the `EVENT_JSON:` prefix.
+#[derive(Serialize)]
+pub struct EventLog<'a> {
+    standard: &'a str,
+    version: &'a str,
+    #[serde(flatten)]
+    event: ChatEvent<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ChatEvent<'a> {
+    ChannelCreated(Vec<ChannelEventData<'a>>),
+    MessagePosted(Vec<MessageEventData<'a>>),
+    MessageEdited(Vec<MessageEventData<'a>>),
+    MessageDeleted(Vec<MessageEventData<'a>>),
+    MessageRejected(Vec<RejectedMessageEventData<'a>>),
+}
+
+#[derive(Serialize)]
+pub struct ChannelEventData<'a> {
+    channel_id: &'a ChannelId,
+}
+
+#[derive(Serialize)]
+pub struct MessageEventData<'a> {
+    channel_id: &'a ChannelId,
+    message_index: u64,
+    sender_id: &'a AccountId,
+}
+
+#[derive(Serialize)]
+pub struct RejectedMessageEventData<'a> {
+    sender_id: &'a AccountId,
+    /// The type of the message envelope.
+    message_type: &'a str,
+    reason: &'a str,
+}
+
+#[derive(Deserialize)]
+pub enum IncomingMessage {
+    ChatMessage {
+        channel_id: ChannelId,
+        body: MessageBody,
+        #[serde(default)]
+        attachments: Vec<Attachment>,
+    },
+    ReplyMessage {
+        channel_id: ChannelId,
+        reply_to: u64,
+        body: MessageBody,
+        #[serde(default)]
+        attachments: Vec<Attachment>,
+    },
+    EditMessage {
+        channel_id: ChannelId,
+        message_index: u64,
+        new_body: MessageBody,
+    },
+    DeleteMessage {
+        channel_id: ChannelId,
+        message_index: u64,
+    },
+    ModDelete {
+        channel_id: ChannelId,
+        message_index: u64,
+        reason: String,
+    },
+    /// Bans the account from posting to the channel. Only moderators can ban accounts.
+    BanAccount {
+        channel_id: ChannelId,
+        account_id: AccountId,
+        reason: String,
+    },
+    UnbanAccount {
+        channel_id: ChannelId,
+        account_id: AccountId,
+    },
+    /// Reports the message to the moderators of the channel. The message is hidden once it has
+    /// enough reports.
+    Report {
+        channel_id: ChannelId,
+        message_index: u64,
+        reason: String,
+    },
+    /// Closes the reports of the message. Moderators either remove the message or restore it.
+    ResolveReport {
+        channel_id: ChannelId,
+        message_index: u64,
+        remove: bool,
+    },
+    SetReportThreshold {
+        channel_id: ChannelId,
+        report_threshold: u64,
+    },
+    SetMinReputation {
+        channel_id: ChannelId,
+        min_reputation: u64,
+    },
+    /// Prevents the account from posting to the channel for the given time. Only moderators can
+    /// mute accounts.
+    MuteAccount {
+        channel_id: ChannelId,
+        account_id: AccountId,
+        duration_ms: u64,
+    },
+    SetArchived {
+        channel_id: ChannelId,
+        archived: bool,
+    },
+    /// Sets whether only the owner and moderators can post to the channel.
+    SetAnnouncement {
+        channel_id: ChannelId,
+        announcement: bool,
+    },
+    /// Sets the minimum time in milliseconds between two messages from the same sender. Owners and
+    /// moderators are not limited.
+    SetSlowMode {
+        channel_id: ChannelId,
+        slow_mode_ms: u64,
+    },
+    SetRetention {
+        channel_id: ChannelId,
+        retention: RetentionPolicy,
+    },
+    /// Sets or removes the price of joining the channel.
+    SetSubscription {
+        channel_id: ChannelId,
+        subscription: Option<Subscription>,
+    },
+    /// Sets or removes the gate accounts have to pass to join the channel.
+    SetGate {
+        channel_id: ChannelId,
+        gate: Option<ChannelGate>,
+    },
+    SetChannelMeta {
+        channel_id: ChannelId,
+        topic: String,
+        description: String,
+    },
+    /// Replaces the contracts notified of new messages in the channel.
+    SetReceivers {
+        channel_id: ChannelId,
+        receivers: Vec<AccountId>,
+    },
+    /// Replaces the tags of the channel.
+    SetTags {
+        channel_id: ChannelId,
+        tags: Vec<Tag>,
+    },
+    /// Registers the image with the IPFS content identifier as the custom emoji `:name:` of the
+    /// channel, or removes the emoji without a CID.
+    SetCustomEmoji {
+        channel_id: ChannelId,
+        name: String,
+        cid: Option<String>,
+    },
+    SetChannelKind {
+        channel_id: ChannelId,
+        kind: ChannelKind,
+    },
+    AddMember {
+        channel_id: ChannelId,
+        account_id: AccountId,
+    },
+    RemoveMember {
+        channel_id: ChannelId,
+        account_id: AccountId,
+    },
+    /// Invites an account to a private channel. Any member can invite.
+    Invite {
+        channel_id: ChannelId,
+        account_id: AccountId,
+    },
+    AcceptInvite {
+        channel_id: ChannelId,
+    },
+    JoinChannel {
+        channel_id: ChannelId,
+    },
+    LeaveChannel {
+        channel_id: ChannelId,
+    },
+    /// Posts a message to the direct channel between the sender and the recipient.
+    DirectMessage {
+        recipient_id: AccountId,
+        body: MessageBody,
+        #[serde(default)]
+        attachments: Vec<Attachment>,
+    },
+    /// Creates a group conversation with the sender and the given members. The title is stored as
+    /// the channel topic.
+    CreateGroup {
+        members: Vec<AccountId>,
+        title: String,
+    },
+    Forward {
+        from_channel_id: ChannelId,
+        message_index: u64,
+        to_channel_id: ChannelId,
+    },
+    /// Posts the same message to every channel. The message is posted to all channels or to none.
+    /// Returns the result for the first channel.
+    CrossPost {
+        channel_ids: Vec<ChannelId>,
+        body: MessageBody,
+        #[serde(default)]
+        attachments: Vec<Attachment>,
+    },
+    /// Hides messages from the account in views requested by the sender and rejects direct messages
+    /// from it.
+    Block {
+        account_id: AccountId,
+    },
+    Unblock {
+        account_id: AccountId,
+    },
+    /// Marks the sender as active without changing anything else.
+    Heartbeat {},
+    /// Shows the sender as typing in the channel for `TYPING_INDICATOR_MS`.
+    Typing {
+        channel_id: ChannelId,
+    },
+    /// Sets the profile of the sender.
+    SetProfile {
+        display_name: String,
+        #[serde(default)]
+        avatar_url: String,
+        #[serde(default)]
+        bio: String,
+    },
+    /// Sets or removes the base64-encoded X25519 public key other accounts use to encrypt messages
+    /// to the sender.
+    SetEncryptionKey {
+        public_key: Option<String>,
+    },
+    /// Sets or removes the contract notified with `on_mention` of every message mentioning the
+    /// sender.
+    SetMentionReceiver {
+        receiver_id: Option<AccountId>,
+    },
+    /// Sets or removes the base64-encoded ed25519 public key that signs the messages of the sender
+    /// relayed with `post_signed_message`.
+    SetSigningKey {
+        public_key: Option<String>,
+    },
+    MarkRead {
+        channel_id: ChannelId,
+        up_to_index: u64,
+    },
+    React {
+        channel_id: ChannelId,
+        message_index: u64,
+        emoji: Emoji,
+    },
+    Unreact {
+        channel_id: ChannelId,
+        message_index: u64,
+        emoji: Emoji,
+    },
+    /// Posts a poll with one vote per account.
+    CreatePoll {
+        channel_id: ChannelId,
+        question: String,
+        options: Vec<String>,
+        /// Time in milliseconds after which votes are rejected.
+        closes_at: u64,
+        /// With a fungible token contract votes are weighted by the balance of the voter, and are
+        /// cast with `vote_weighted`.
+        #[serde(default)]
+        weight_token_id: Option<AccountId>,
+    },
+    /// Votes in a poll that isn't weighted.
+    Vote {
+        channel_id: ChannelId,
+        message_index: u64,
+        /// The index of the chosen option.
+        option: u64,
+    },
+    /// Forwards the deposit attached to the call to the author of the message, minus the configured
+    /// fee. A batch of messages can contain only one tip.
+    Tip {
+        channel_id: ChannelId,
+        message_index: u64,
+    },
+}
+
+/// Parses an incoming message either in the form `{"ChatMessage": {...}}` or in the envelope form
+/// `{"type": "ChatMessage", "payload": {...}}`. Returns the type of an envelope with a type this
+/// contract doesn't support as the error, so newer clients don't fail against older deployments.
+fn parse_incoming_message(message: &str) -> Result<IncomingMessage, String> {
+    let value: serde_json::Value = serde_json::from_str(message).expect("Can't parse the message");
+    let message_type = match value.get("type").and_then(|message_type| message_type.as_str()) {
+        Some(message_type) => message_type.to_string(),
+        None => return Ok(serde_json::from_value(value).expect("Can't parse the message")),
+    };
+    if !INCOMING_MESSAGE_KINDS.contains(&message_type.as_str()) {
+        return Err(message_type);
+    }
+    let payload = value.get("payload").cloned().unwrap_or_else(|| serde_json::json!({}));
+    let mut message = serde_json::Map::new();
+    message.insert(message_type, payload);
+    Ok(serde_json::from_value(serde_json::Value::Object(message)).expect("Can't parse the message"))
+}
+
+fn verify_app_id(app_id: &AppId, config: &Config) {
+    if (app_id.len() as u64) < config.min_app_id_len || app_id.len() as u64 > config.max_app_id_len {
+        env::panic(b"App ID length is out of the configured bounds");
+    }
+    for c in app_id.bytes() {
+        match c {
+            b'a'..=b'z' => (),
+            b'0'..=b'9' => (),
+            b'-' | b'_' | b'.' => (),
+            _ => env::panic(
+                b"Unsupported character in the app ID. Only allowed to use `-.|` and 0-9 a-z",
+            ),
+        }
+    }
+}
+
+fn verify_channel_id(channel_id: &ChannelId, config: &Config) {
+    if channel_id.is_empty() || channel_id.len() as u64 > config.max_channel_id_len {
+        env::panic(b"Channel ID length is out of the configured bounds");
+    }
+    for c in channel_id.bytes() {
+        match c {
+            b'a'..=b'z' => (),
+            b'0'..=b'9' => (),
+            b'-' | b'_' | b'.' => (),
+            _ => env::panic(
+                b"Unsupported character in the channel. Only allowed to use `-.|` and 0-9 a-z",
+            ),
+        }
+    }
+}
+
+/// Panics if the channel ID uses a prefix reserved for direct messages or group conversations.
+fn verify_unreserved_channel_id(channel_id: &ChannelId) {
+    if channel_id.starts_with(DIRECT_CHANNEL_PREFIX) {
+        env::panic(b"The channel ID is reserved for direct messages");
+    }
+    if channel_id.starts_with(GROUP_CHANNEL_PREFIX) {
+        env::panic(b"The channel ID is reserved for group conversations");
+    }
+}
+
+fn verify_cid(cid: &str) {
+    if cid.len() < 46 || cid.len() > 128 {
+        env::panic(b"CID length should be between 46 and 128 characters");
+    }
+    if !cid.bytes().all(|c| c.is_ascii_alphanumeric()) {
+        env::panic(b"Unsupported character in the CID. Only allowed to use 0-9 a-z A-Z");
+    }
+}
+
+fn verify_custom_emoji_name(name: &str) {
+    if name.len() < 2 || name.len() > 32 {
+        env::panic(b"Custom emoji name length should be between 2 and 32 characters");
+    }
+    if !name.bytes().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_' || c == b'-') {
+        env::panic(b"Unsupported character in the custom emoji name. Only allowed to use `-_` and 0-9 a-z");
+    }
+}
+
+fn verify_mime(mime: &str) {
+    if mime.is_empty() || mime.len() > 128 {
+        env::panic(b"MIME type length should be between 1 and 128 characters");
+    }
+    let mut parts = mime.splitn(2, '/');
+    let (media_type, subtype) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+    if media_type.is_empty() || subtype.is_empty() {
+        env::panic(b"MIME type should have the form `type/subtype`");
+    }
+}
+
+fn verify_url(url: &str) {
+    if url.len() > 2048 {
+        env::panic(b"URL length should be at most 2048 characters");
+    }
+    let host = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or_else(|| env::panic(b"URL should start with `http://` or `https://`"));
+    if host.is_empty() || host.starts_with('/') {
+        env::panic(b"URL should have a host");
+    }
+    if url.bytes().any(|c| c.is_ascii_whitespace() || c.is_ascii_control()) {
+        env::panic(b"URL should not contain whitespace or control characters");
+    }
+}
+
+fn verify_message_text(text: &str, config: &Config) {
+    if text.len() as u64 > config.max_message_len {
+        env::panic(b"Message text is too long");
+    }
+}
+
+fn verify_message_body(body: &MessageBody, sender_id: &AccountId, config: &Config) {
+    match body {
+        MessageBody::Text { text } => verify_message_text(text, config),
+        MessageBody::Image { cid, mime } => {
+            verify_cid(cid);
+            verify_mime(mime);
+        },
+        MessageBody::Link { url, title } => {
+            verify_url(url);
+            if title.len() > 256 {
+                env::panic(b"Link title length should be at most 256 characters");
+            }
+        },
+        MessageBody::System { text } => {
+            verify_message_text(text, config);
+            if sender_id != &env::current_account_id() {
+                env::panic(b"Only the contract can post system messages");
+            }
+        },
+        MessageBody::Poll { .. } => env::panic(b"Polls should be created with `CreatePoll`"),
+        MessageBody::Encrypted { ciphertext_b64, key_ids } => {
+            if ciphertext_b64.len() as u64 > config.max_message_len {
+                env::panic(b"Ciphertext is too long");
+            }
+            if key_ids.len() as u64 > config.max_encryption_key_ids {
+                env::panic(b"Too many key IDs");
+            }
+        },
+    }
+}
+
+fn verify_poll(question: &str, options: &[String], closes_at: u64, time: u64) {
+    if question.is_empty() || question.len() > 256 {
+        env::panic(b"Poll question length should be between 1 and 256 characters");
+    }
+    if options.len() < 2 || options.len() > MAX_POLL_OPTIONS {
+        env::panic(b"Poll should have between 2 and 10 options");
+    }
+    if options.iter().any(|option| option.is_empty() || option.len() > 100) {
+        env::panic(b"Poll option length should be between 1 and 100 characters");
+    }
+    if closes_at <= time {
+        env::panic(b"Poll should close in the future");
+    }
+}
+
+fn verify_banned_word(word: &str) {
+    if word.is_empty() || word.len() > 64 {
+        env::panic(b"Banned word length should be between 1 and 64 characters");
+    }
+}
+
+fn verify_attachments(attachments: &[Attachment], config: &Config) {
+    if attachments.len() as u64 > config.max_num_attachments {
+        env::panic(b"Too many attachments");
+    }
+    for attachment in attachments {
+        verify_cid(&attachment.cid);
+        verify_mime(&attachment.mime);
+        if attachment.size > config.max_attachment_size {
+            env::panic(b"Attachment is too large");
+        }
+    }
+}
+
+fn verify_config(config: &Config) {
+    if config.message_deposit.parse::<Balance>().is_err() {
+        env::panic(b"Message deposit should be a decimal number");
+    }
+    if config.tip_fee_bps > 10_000 || config.subscription_fee_bps > 10_000 {
+        env::panic(b"Fees should be at most 10000 basis points");
+    }
+    if config.default_page_size == 0 {
+        env::panic(b"Default page size should be positive");
+    }
+}
+
+/// Reduces the requested number of items to the configured page size. A zero limit requests the
+/// default page size.
+fn page_limit(limit: u64, config: &Config) -> u64 {
+    let limit = if limit == 0 { config.default_page_size } else { limit };
+    limit.min(config.max_page_size)
+}
+
+fn verify_topic(topic: &str) {
+    if topic.len() > 256 {
+        env::panic(b"Topic length should be at most 256 characters");
+    }
+}
+
+fn verify_receivers(receivers: &[AccountId], config: &Config) {
+    if receivers.len() as u64 > config.max_channel_receivers {
+        env::panic(b"Too many receivers");
+    }
+    for (index, receiver_id) in receivers.iter().enumerate() {
+        verify_receiver_id(receiver_id);
+        if receivers[..index].contains(receiver_id) {
+            env::panic(b"Duplicate receiver");
+        }
+    }
+}
+
+fn verify_receiver_id(receiver_id: &AccountId) {
+    if receiver_id.len() < 2 || receiver_id.len() > 64 {
+        env::panic(b"Receiver account ID length should be between 2 and 64 characters");
+    }
+}
+
+fn verify_tags(tags: &[Tag], config: &Config) {
+    if tags.len() as u64 > config.max_num_tags {
+        env::panic(b"Too many tags");
+    }
+    for (index, tag) in tags.iter().enumerate() {
+        if tag.is_empty() || tag.len() > 32 {
+            env::panic(b"Tag length should be between 1 and 32 characters");
+        }
+        if !tag.bytes().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-') {
+            env::panic(b"Unsupported character in the tag. Only allowed to use `-` and 0-9 a-z");
+        }
+        if tags[..index].contains(tag) {
+            env::panic(b"Duplicate tag");
+        }
+    }
+}
+
+fn verify_subscription(subscription: &Subscription) {
+    if subscription.price.parse::<Balance>().is_err() {
+        env::panic(b"Subscription price should be a decimal number");
+    }
+    if subscription.period_ms == 0 {
+        env::panic(b"Subscription period should be positive");
+    }
+}
+
+fn verify_gate(gate: &ChannelGate) {
+    match gate {
+        ChannelGate::FungibleToken { token_id, min_balance } => {
+            if token_id.len() < 2 || token_id.len() > 64 {
+                env::panic(b"Token account ID length should be between 2 and 64 characters");
+            }
+            if min_balance.parse::<u128>().is_err() {
+                env::panic(b"Minimum balance should be a decimal number");
+            }
+        },
+        ChannelGate::NonFungibleToken { contract_id } => {
+            if contract_id.len() < 2 || contract_id.len() > 64 {
+                env::panic(b"NFT contract account ID length should be between 2 and 64 characters");
+            }
+        },
+    }
+}
+
+fn verify_profile(profile: &Profile) {
+    if profile.display_name.len() > 64 {
+        env::panic(b"Display name length should be at most 64 characters");
+    }
+    if !profile.avatar_url.is_empty() {
+        verify_url(&profile.avatar_url);
+    }
+    if profile.bio.len() > 256 {
+        env::panic(b"Bio length should be at most 256 characters");
+    }
+}
+
+fn verify_app_metadata(metadata: &AppMetadata) {
+    if metadata.description.len() > 256 {
+        env::panic(b"Description length should be at most 256 characters");
+    }
+    if !metadata.icon_url.is_empty() {
+        verify_url(&metadata.icon_url);
+    }
+}
+
+fn verify_encryption_key(public_key: &str) {
+    match base64::decode(public_key) {
+        Ok(bytes) if bytes.len() == 32 => (),
+        _ => env::panic(b"Encryption key should be a base64-encoded 32-byte X25519 public key"),
+    }
+}
+
+/// Decodes the base64-encoded ed25519 public key.
+fn verifying_key(public_key: &str) -> VerifyingKey {
+    let bytes = base64::decode(public_key).ok().filter(|bytes| bytes.len() == 32);
+    bytes
+        .and_then(|bytes| {
+            let mut key = [0; 32];
+            key.copy_from_slice(&bytes);
+            VerifyingKey::from_bytes(&key).ok()
+        })
+        .unwrap_or_else(|| env::panic(b"Signing key should be a base64-encoded 32-byte ed25519 public key"))
+}
+
+/// Panics unless the base64-encoded signature is a valid ed25519 signature of the payload by the key.
+fn verify_signature(payload: &[u8], signature: &str, public_key: &VerifyingKey) {
+    let signature = match base64::decode(signature) {
+        Ok(bytes) if bytes.len() == 64 => {
+            let mut signature = [0; 64];
+            signature.copy_from_slice(&bytes);
+            Signature::from_bytes(&signature)
+        },
+        _ => env::panic(b"Signature should be a base64-encoded 64-byte ed25519 signature"),
+    };
+    if public_key.verify_strict(payload, &signature).is_err() {
+        env::panic(b"Invalid signature");
+    }
+}
+
+fn verify_emoji(emoji: &Emoji) {
+    if emoji.is_empty() || emoji.len() > 32 {
+        env::panic(b"Emoji length should be between 1 and 32 bytes");
+    }
+}
+
+/// Returns unique account IDs mentioned in the text as `@account.near`.
+fn extract_mentions(text: &str) -> Vec<AccountId> {
+    let mut mentions: Vec<AccountId> = Vec::new();
+    for part in text.split('@').skip(1) {
+        let account_id = part
+            .split(|c: char| !(c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c)))
+            .next()
+            .unwrap()
+            .trim_end_matches('.');
+        if account_id.len() >= 2 && account_id.len() <= 64 && !mentions.iter().any(|m| m == account_id) {
+            mentions.push(account_id.to_string());
+        }
+    }
+    mentions
+}
+
+/// Returns the ID of the direct channel between two accounts: `dm-` followed by the hex-encoded
+/// sha256 of the two account IDs sorted and separated by a space.
+fn direct_channel_id(account_a: &AccountId, account_b: &AccountId) -> ChannelId {
+    let (first, second) = if account_a < account_b { (account_a, account_b) } else { (account_b, account_a) };
+    let hash = env::sha256(format!("{} {}", first, second).as_bytes());
+    format!("{}{}", DIRECT_CHANNEL_PREFIX, hex_encode(&hash))
+}
+
+/// Compact encoding of view responses for native clients.
+fn borsh_base64<T: BorshSerialize>(value: &T) -> String {
+    base64::encode(&value.try_to_vec().unwrap())
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the Merkle root over the leaves as described in `HistoryCommitment`.
+fn merkle_root(mut nodes: Vec<Vec<u8>>) -> Vec<u8> {
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => env::sha256(&[left.as_slice(), right.as_slice()].concat()),
+                _ => pair[0].clone(),
+            })
+            .collect();
+    }
+    nodes.pop().unwrap_or_default()
+}
+
+fn app_key(app_id: &AppId, key: &Key) -> Vec<u8> {
+    let app_id_hash = env::sha256(app_id.as_bytes());
+    let key_hash = env::sha256(key.as_bytes());
+    let mut res = Vec::with_capacity(app_id_hash.len() + key_hash.len() + 1);
+    res.push(b'a');
+    res.extend(app_id_hash);
+    res.extend(key_hash);
+    res
+}
+
+
+fn messages_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'm');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn threads_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b't');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn replies_key_from_hash(channel_hash: ChannelHash, root_index: u64) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 9);
+    res.push(b'r');
+    res.extend_from_slice(&channel_hash);
+    res.extend_from_slice(&root_index.to_le_bytes());
+    res
+}
+
+fn revisions_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'h');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn message_revisions_key_from_hash(channel_hash: ChannelHash, message_index: u64) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 9);
+    res.push(b'v');
+    res.extend_from_slice(&channel_hash);
+    res.extend_from_slice(&message_index.to_le_bytes());
+    res
+}
+
+fn mentions_key(account_id: &AccountId) -> Vec<u8> {
+    let account_hash = env::sha256(account_id.as_bytes());
+    let mut res = Vec::with_capacity(account_hash.len() + 1);
+    res.push(b'n');
+    res.extend(account_hash);
+    res
+}
+
+fn read_marker_key(account_id: &AccountId, channel_hash: &[u8]) -> ReadMarkerKey {
+    account_channel_key(account_id, channel_hash)
+}
+
+fn account_channel_key(account_id: &AccountId, channel_hash: &[u8]) -> Vec<u8> {
+    let account_hash = env::sha256(account_id.as_bytes());
+    let mut res = Vec::with_capacity(account_hash.len() + channel_hash.len());
+    res.extend(account_hash);
+    res.extend_from_slice(channel_hash);
+    res
+}
+
+fn moderators_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'o');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn moderation_log_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'g');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn members_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'p');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn last_posts_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'w');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn bans_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'x');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn mutes_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'y');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn tips_received_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'X');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn subscriptions_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'E');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn history_commitments_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'3');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn chain_links_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'4');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn daily_activity_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'5');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn num_messages_by_sender_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'6');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn reports_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_hash.len() + 1);
+    res.push(b'Q');
+    res.extend_from_slice(&channel_hash);
+    res
+}
+
+fn invites_key(account_id: &AccountId) -> Vec<u8> {
+    let account_hash = env::sha256(account_id.as_bytes());
+    let mut res = Vec::with_capacity(account_hash.len() + 1);
+    res.push(b'q');
+    res.extend(account_hash);
+    res
+}
+
+fn account_channels_key(account_id: &AccountId) -> Vec<u8> {
+    let account_hash = env::sha256(account_id.as_bytes());
+    let mut res = Vec::with_capacity(account_hash.len() + 1);
+    res.push(b'j');
+    res.extend(account_hash);
+    res
+}
+
+fn tag_channels_key(tag: &Tag) -> Vec<u8> {
+    let tag_hash = env::sha256(tag.as_bytes());
+    let mut res = Vec::with_capacity(tag_hash.len() + 1);
+    res.push(b'i');
+    res.extend(tag_hash);
+    res
+}
+
+fn app_keys_key(app_id: &AppId) -> Vec<u8> {
+    let app_id_hash = env::sha256(app_id.as_bytes());
+    let mut res = Vec::with_capacity(app_id_hash.len() + 1);
+    res.push(b'I');
+    res.extend(app_id_hash);
+    res
+}
+
+fn app_inbox_key(app_id: &AppId) -> Vec<u8> {
+    let app_id_hash = env::sha256(app_id.as_bytes());
+    let mut res = Vec::with_capacity(app_id_hash.len() + 1);
+    res.push(b'8');
+    res.extend(app_id_hash);
+    res
+}
+
+fn app_writers_key(app_id: &AppId) -> Vec<u8> {
+    let app_id_hash = env::sha256(app_id.as_bytes());
+    let mut res = Vec::with_capacity(app_id_hash.len() + 1);
+    res.push(b'J');
+    res.extend(app_id_hash);
+    res
+}
+
+fn token_balances_key(account_id: &AccountId) -> Vec<u8> {
+    let account_hash = env::sha256(account_id.as_bytes());
+    let mut res = Vec::with_capacity(account_hash.len() + 1);
+    res.push(b'K');
+    res.extend(account_hash);
+    res
+}
+
+fn blocked_accounts_key(account_id: &AccountId) -> Vec<u8> {
+    let account_hash = env::sha256(account_id.as_bytes());
+    let mut res = Vec::with_capacity(account_hash.len() + 1);
+    res.push(b'N');
+    res.extend(account_hash);
+    res
+}
+
+fn channel_key(channel_id: &ChannelId) -> Vec<u8> {
+    let mut res = Vec::with_capacity(channel_id.len() + 1);
+    res.push(b'%');
+    res.extend(channel_id.as_bytes());
+    res
+}
+
+fn message_key(channel_hash: &[u8], message_index: u64) -> MessageKey {
+    let mut res = Vec::with_capacity(channel_hash.len() + 8);
+    res.extend_from_slice(channel_hash);
+    res.extend_from_slice(&message_index.to_le_bytes());
+    res
+}
+
+fn emojis_key(message_key: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(message_key.len() + 1);
+    res.push(b'f');
+    res.extend_from_slice(message_key);
+    res
+}
+
+fn poll_voters_key(message_key: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(message_key.len() + 1);
+    res.push(b'C');
+    res.extend_from_slice(message_key);
+    res
+}
+
+fn reactors_key(message_key: &[u8], emoji: &Emoji) -> Vec<u8> {
+    let emoji_hash = env::sha256(emoji.as_bytes());
+    let mut res = Vec::with_capacity(message_key.len() + emoji_hash.len() + 1);
+    res.push(b's');
+    res.extend_from_slice(message_key);
+    res.extend(emoji_hash);
+    res
+}
+
+impl Default for MetanearChat {
+    fn default() -> Self {
+        env::panic(b"Not initialized yet.");
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_message_len: 4096,
+            max_num_attachments: 10,
+            max_attachment_size: 100 * 1024 * 1024,
+            max_num_mentions: 10,
+            max_batch_size: 20,
+            max_group_size: 50,
+            max_num_tags: 5,
+            min_app_id_len: 2,
+            max_app_id_len: 64,
+            max_channel_id_len: 128,
+            max_posts_per_window: 20,
+            post_window_ms: 60_000,
+            max_page_size: 100,
+            default_page_size: 20,
+            message_deposit: "0".to_string(),
+            message_deposit_cooldown_ms: 24 * 60 * 60 * 1000,
+            tip_fee_bps: 0,
+            subscription_fee_bps: 0,
+            max_custom_emoji: 50,
+            max_crosspost_channels: 5,
+            max_channel_receivers: 3,
+            max_encryption_key_ids: 50,
+        }
+    }
+}
+
+fn event_log(event: ChatEvent) -> String {
+    let event_log = EventLog {
+        standard: EVENT_STANDARD,
+        version: EVENT_STANDARD_VERSION,
+        event,
+    };
+    format!("EVENT_JSON:{}", serde_json::to_string(&event_log).unwrap())
+}
+
+/// Calls the method of the receiver without waiting for the result, unless the call doesn't have
+/// enough gas left for the notification and `GAS_RESERVED_AFTER_NOTIFICATIONS`.
+fn notify(receiver_id: AccountId, method_name: &[u8], args: serde_json::Value, gas: Gas) {
+    let remaining_gas = env::prepaid_gas().saturating_sub(env::used_gas());
+    if remaining_gas >= gas + GAS_RESERVED_AFTER_NOTIFICATIONS {
+        Promise::new(receiver_id).function_call(method_name.to_vec(), args.to_string().into_bytes(), 0, gas);
+    }
+}
+
+fn emit_event(event: ChatEvent) {
+    env::log(event_log(event).as_bytes());
+}
+
+/// Logs that the message of an unsupported type was skipped. The result doesn't target a channel.
+fn reject_message(sender_id: &AccountId, message_type: &str, time: u64) -> PostResult {
+    emit_event(ChatEvent::MessageRejected(vec![RejectedMessageEventData {
+        sender_id,
+        message_type,
+        reason: "Unknown message type",
+    }]));
+    PostResult { channel_id: String::new(), message_index: 0, time }
+}
+
+fn assert_self() {
+    assert_eq!(env::current_account_id(), env::predecessor_account_id(), "Self calls only");
+}
+
+#[near_bindgen]
+impl MetanearChat {
+    /// Initializes the contract administered by the given owner and creates the given channels owned
+    /// by the contract account.
+    #[init]
+    pub fn new(owner_id: AccountId, bootstrap_channels: Option<Vec<BootstrapChannel>>) -> Self {
+        assert!(env::state_read::<MetanearChat>().is_none(), "The contract is already initialized");
+        let mut contract = Self::empty(owner_id);
+        for bootstrap_channel in bootstrap_channels.unwrap_or_default() {
+            verify_topic(&bootstrap_channel.topic);
+            let mut channel = contract.get_channel(bootstrap_channel.channel_id);
+            channel.topic = bootstrap_channel.topic;
+            contract.save_channel(&channel);
+        }
+        contract
+    }
+
+    /// Upgrades the state written by a previous version of the contract to the current layout. Must
+    /// be called by the contract account right after the new code is deployed. Channels of the first
+    /// layout are converted afterwards by `migrate_channels`.
+    #[init]
+    pub fn migrate(owner_id: AccountId) -> Self {
+        assert_self();
+        match VersionedState::read() {
+            VersionedState::Current(contract) => *contract,
+            VersionedState::V1(old) => {
+                let mut contract = Self::empty(owner_id);
+                contract.legacy_channels = old.channels;
+                contract.total_num_messages = old.total_num_messages;
+                contract
+            }
+        }
+    }
+
+    /// Transfers the administration of the contract to another account.
+    pub fn set_owner(&mut self, owner_id: AccountId) {
+        self.assert_owner();
+        self.owner_id = owner_id;
+    }
+
+    /// Rejects all changes except for admin methods until the contract is unpaused. Views keep
+    /// working.
+    pub fn pause(&mut self) {
+        self.assert_owner();
+        self.paused = true;
+    }
+
+    pub fn unpause(&mut self) {
+        self.assert_owner();
+        self.paused = false;
+    }
+
+    /// Sends the amount in yoctoNEAR from the treasury to the receiver.
+    pub fn withdraw_treasury(&mut self, amount: String, receiver_id: AccountId) -> Promise {
+        self.assert_owner();
+        let amount: Balance = amount.parse().expect("Invalid amount");
+        assert!(amount <= self.treasury.balance, "The amount is greater than the treasury balance");
+        self.treasury.balance -= amount;
+        self.treasury.total_withdrawn += amount;
+        Promise::new(receiver_id).transfer(amount)
+    }
+
+    pub fn get_treasury(&self) -> TreasuryView {
+        TreasuryView {
+            balance: self.treasury.balance.to_string(),
+            total_collected: self.treasury.total_collected.to_string(),
+            total_withdrawn: self.treasury.total_withdrawn.to_string(),
+        }
+    }
+
+    pub fn update_config(&mut self, config: Config) {
+        self.assert_owner();
+        verify_config(&config);
+        self.config = config;
+    }
+
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn add_moderator(&mut self, channel_id: ChannelId, account_id: AccountId) {
+        self.assert_not_paused();
+        let predecessor_id = env::predecessor_account_id();
+        let mut channel = self.get_accessible_channel(channel_id, Some(&predecessor_id));
+        channel.assert_owner(&predecessor_id);
+        channel.moderators.insert(&account_id);
+        self.save_channel(&channel);
+    }
+
+    pub fn remove_moderator(&mut self, channel_id: ChannelId, account_id: AccountId) {
+        self.assert_not_paused();
+        let predecessor_id = env::predecessor_account_id();
+        let mut channel = self.get_accessible_channel(channel_id, Some(&predecessor_id));
+        channel.assert_owner(&predecessor_id);
+        assert!(channel.moderators.remove(&account_id), "Not a moderator");
+        self.save_channel(&channel);
+    }
+
+    /// Checks whether the predecessor passes the gate of the channel and adds them as a member in
+    /// the callback if they do.
+    pub fn join_gated_channel(&mut self, channel_id: ChannelId) -> Promise {
+        self.assert_not_paused();
+        let account_id = env::predecessor_account_id();
+        let channel = self.get_channel(channel_id);
+        assert!(!channel.is_member(&account_id), "Already a member");
+        let gate_check = match channel.gate.expect("The channel is not gated") {
+            ChannelGate::FungibleToken { token_id, .. } => Promise::new(token_id).function_call(
+                b"ft_balance_of".to_vec(),
+                serde_json::json!({ "account_id": account_id }).to_string().into_bytes(),
+                0,
+                GAS_FOR_GATE_CHECK,
+            ),
+            ChannelGate::NonFungibleToken { contract_id } => Promise::new(contract_id).function_call(
+                b"nft_tokens_for_owner".to_vec(),
+                serde_json::json!({ "account_id": account_id, "from_index": "0", "limit": 1 }).to_string().into_bytes(),
+                0,
+                GAS_FOR_GATE_CHECK,
+            ),
+        };
+        gate_check.then(Promise::new(env::current_account_id()).function_call(
+            b"on_gate_check".to_vec(),
+            serde_json::json!({ "channel_id": channel.channel_id, "account_id": account_id }).to_string().into_bytes(),
+            0,
+            GAS_FOR_GATE_CALLBACK,
+        ))
+    }
+
+    /// Callback for `join_gated_channel`. Returns whether the account joined the channel.
+    pub fn on_gate_check(&mut self, channel_id: ChannelId, account_id: AccountId) -> bool {
+        assert_self();
+        self.assert_not_paused();
+        let mut channel = self.get_channel(channel_id);
+        let passed = match (env::promise_result(0), &channel.gate) {
+            (PromiseResult::Successful(result), Some(gate)) => gate.is_passed(&result),
+            _ => false,
+        };
+        if passed {
+            self.add_member(&mut channel, &account_id, env::block_timestamp() / 1000000);
+            self.save_channel(&channel);
+        }
+        passed
+    }
+
+    /// Fetches the balance of the predecessor in the token of the weighted poll and records the vote
+    /// weighted by it in the callback. The storage used by the vote is paid from the storage balance
+    /// of the predecessor. The attached deposit is added to the storage balance first.
+    pub fn vote_weighted(&mut self, channel_id: ChannelId, message_index: u64, option: u64) -> Promise {
+        self.assert_not_paused();
+        let account_id = env::predecessor_account_id();
+        assert!(self.global_bans.get(&account_id).is_none(), "The account is banned from the contract");
+        let channel = self.get_accessible_channel(channel_id, Some(&account_id));
+        let (_, weight_token_id) = channel.poll_to_vote(message_index, option, env::block_timestamp() / 1000000);
+        let weight_token_id = weight_token_id.expect("The poll is not weighted");
+        self.add_attached_deposit(&account_id);
+        Promise::new(weight_token_id)
+            .function_call(
+                b"ft_balance_of".to_vec(),
+                serde_json::json!({ "account_id": account_id }).to_string().into_bytes(),
+                0,
+                GAS_FOR_VOTE_WEIGHT,
+            )
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"on_vote_weight".to_vec(),
+                serde_json::json!({
+                    "channel_id": channel.channel_id,
+                    "message_index": message_index,
+                    "account_id": account_id,
+                    "option": option,
+                })
+                .to_string()
+                .into_bytes(),
+                0,
+                GAS_FOR_VOTE_CALLBACK,
+            ))
+    }
+
+    /// Callback for `vote_weighted`. Returns whether the vote was recorded, which requires a positive
+    /// balance.
+    pub fn on_vote_weight(
+        &mut self,
+        channel_id: ChannelId,
+        message_index: u64,
+        account_id: AccountId,
+        option: u64,
+    ) -> bool {
+        assert_self();
+        self.assert_not_paused();
+        let weight = match env::promise_result(0) {
+            PromiseResult::Successful(result) => serde_json::from_slice::<String>(&result)
+                .ok()
+                .and_then(|balance| balance.parse::<Balance>().ok())
+                .unwrap_or(0),
+            _ => 0,
+        };
+        if weight == 0 {
+            return false;
+        }
+        let channel = self.get_channel(channel_id);
+        let initial_storage_usage = env::storage_usage();
+        self.vote(&channel, message_index, &account_id, option, Some(weight), env::block_timestamp() / 1000000);
+        self.charge_storage(&account_id, initial_storage_usage);
+        true
+    }
+
+    /// Converts and moves up to `limit` messages of the channels of the first storage layout, where
+    /// channels were keyed by the channel hash. Chat requests fail until the migration is complete.
+    /// Returns the number of channels left to migrate.
+    pub fn migrate_channels(&mut self, limit: u64) -> u64 {
+        self.assert_owner();
+        let mut num_migrated = 0;
+        while num_migrated < limit && self.legacy_channels.len() > 0 {
+            let channel_hash = self.legacy_channels.keys_as_vector().get(self.legacy_channels.len() - 1).unwrap();
+            let old_channel = self.legacy_channels.get(&channel_hash).unwrap();
+            let mut channel = self
+                .read_channel(&old_channel.channel_id)
+                .unwrap_or_else(|| Channel::new(old_channel.channel_id.clone(), env::current_account_id()));
+            // The number of converted messages is the cursor of a partially migrated channel. The
+            // messages keep their storage prefix, so every converted message overwrites the old one
+            // after it has been read.
+            while num_migrated < limit && channel.messages.len() < old_channel.messages.len() {
+                let old_message = old_channel.messages.get(channel.messages.len()).unwrap();
+                if channel.messages.is_empty() {
+                    channel.created_at = old_message.time;
+                }
+                let body = MessageBody::Text { text: old_message.text };
+                channel.add_message(Message::new(old_message.sender_id, body, vec![], old_message.time));
+                num_migrated += 1;
+            }
+            self.write_channel(&channel);
+            if channel.messages.len() == old_channel.messages.len() {
+                self.legacy_channels.remove(&channel_hash);
+                num_migrated += u64::from(old_channel.messages.is_empty());
+            }
+        }
+        self.legacy_channels.len()
+    }
+
+    /// Returns the deposit held for the message to the storage balance of its sender once the
+    /// cool-down has passed. Can be called by anyone.
+    pub fn release_message_deposit(&mut self, channel_id: ChannelId, message_index: u64) {
+        self.assert_not_paused();
+        let key = message_key(&env::sha256(channel_id.as_bytes()), message_index);
+        let deposit = self.message_deposits.get(&key).expect("No deposit is held for the message");
+        assert!(
+            env::block_timestamp() / 1000000 >= deposit.release_time,
+            "The message deposit can't be released yet"
+        );
+        self.message_deposits.remove(&key);
+        let balance = self.storage_balances.get(&deposit.account_id).unwrap_or(0);
+        self.storage_balances.insert(&deposit.account_id, &(balance + deposit.amount));
+    }
+
+    /// Prunes up to `limit` of the oldest messages of the channel that exceed its retention policy.
+    /// Returns the number of pruned messages.
+    pub fn prune_channel(&mut self, channel_id: ChannelId, limit: u64) -> u64 {
+        self.assert_not_paused();
+        self.assert_migrated();
+        let mut channel = self.read_channel(&channel_id).expect("Channel doesn't exist");
+        let pruned = channel.prune(env::block_timestamp() / 1000000, limit);
+        self.save_channel(&channel);
+        let num_pruned = pruned.len() as u64;
+        for freed_storage in pruned {
+            self.refund_storage(freed_storage);
+        }
+        num_pruned
+    }
+
+    pub fn master_set(&mut self, app_id: AppId, key: Key, value: Value) {
+        self.assert_app_writer(&app_id);
+        self.set_app_values(&app_id, vec![(key, value)]);
+    }
+
+    pub fn master_remove(&mut self, app_id: AppId, key: Key) {
+        self.assert_app_writer(&app_id);
+        self.remove_app_values(&app_id, vec![key]);
+    }
+
+    /// Registers the app owned by the predecessor. The storage used by the app is paid from the
+    /// storage balance of the predecessor. An app that already has keys can only be registered by
+    /// the contract owner.
+    pub fn register_app(&mut self, app_id: AppId, metadata: AppMetadata) {
+        verify_app_id(&app_id, &self.config);
+        assert_ne!(app_id.as_bytes(), CHAT_APP_ID, "The chat app can't have an owner");
+        verify_app_metadata(&metadata);
+        assert!(self.apps.get(&app_id).is_none(), "The app is already registered");
+        let owner_id = env::predecessor_account_id();
+        if self.app_keys.get(&app_id).map(|keys| keys.len() > 0).unwrap_or(false) {
+            self.assert_owner();
+        }
+
+        self.add_attached_deposit(&owner_id);
+        let initial_storage_usage = env::storage_usage();
+        self.apps.insert(&app_id, &App {
+            owner_id: owner_id.clone(),
+            writers: Set::new(app_writers_key(&app_id)),
+            metadata,
+        });
+        self.charge_storage(&owner_id, initial_storage_usage);
+    }
+
+    pub fn update_app_metadata(&mut self, app_id: AppId, metadata: AppMetadata) {
+        verify_app_metadata(&metadata);
+        let mut app = self.get_app_as_owner(&app_id);
+        let sender_id = env::predecessor_account_id();
+        self.add_attached_deposit(&sender_id);
+        let initial_storage_usage = env::storage_usage();
+        app.metadata = metadata;
+        self.apps.insert(&app_id, &app);
+        self.charge_storage(&sender_id, initial_storage_usage);
+    }
+
+    pub fn get_app(&self, app_id: AppId) -> Option<AppView> {
+        self.apps.get(&app_id).map(|app| self.app_view(app_id, app))
+    }
+
+    /// Returns registered apps in the order they were registered.
+    pub fn get_apps(&self, from_index: u64, limit: u64) -> Vec<AppView> {
+        let limit = page_limit(limit, &self.config);
+        let app_ids = self.apps.keys_as_vector();
+        let apps = self.apps.values_as_vector();
+        let mut res = Vec::new();
+        let mut index = from_index;
+        while (res.len() as u64) < limit && index < app_ids.len() {
+            res.push(self.app_view(app_ids.get(index).unwrap(), apps.get(index).unwrap()));
+            index += 1;
+        }
+        res
+    }
+
+    /// Makes the account the owner of the app. The contract owner can assign any app, the app owner
+    /// can transfer it.
+    pub fn set_app_owner(&mut self, app_id: AppId, owner_id: AccountId) {
+        verify_app_id(&app_id, &self.config);
+        assert_ne!(app_id.as_bytes(), CHAT_APP_ID, "The chat app can't have an owner");
+        let predecessor_id = env::predecessor_account_id();
+        let mut app = match self.apps.get(&app_id) {
+            Some(app) => {
+                assert!(
+                    predecessor_id == self.owner_id || predecessor_id == app.owner_id,
+                    "Only the app owner can do this"
+                );
+                app
+            }
+            None => {
+                self.assert_owner();
+                App {
+                    owner_id: owner_id.clone(),
+                    writers: Set::new(app_writers_key(&app_id)),
+                    metadata: AppMetadata::default(),
+                }
+            }
+        };
+        app.owner_id = owner_id;
+        self.apps.insert(&app_id, &app);
+    }
+
+    /// Allows the account to write to the app store of the app.
+    pub fn add_app_writer(&mut self, app_id: AppId, account_id: AccountId) {
+        let mut app = self.get_app_as_owner(&app_id);
+        app.writers.insert(&account_id);
+        self.apps.insert(&app_id, &app);
+    }
+
+    pub fn remove_app_writer(&mut self, app_id: AppId, account_id: AccountId) {
+        let mut app = self.get_app_as_owner(&app_id);
+        assert!(app.writers.remove(&account_id), "The account is not a writer");
+        self.apps.insert(&app_id, &app);
+    }
+
+    pub fn get_app_owner(&self, app_id: AppId) -> Option<AccountId> {
+        self.apps.get(&app_id).map(|app| app.owner_id)
+    }
+
+    pub fn get_app_writers(&self, app_id: AppId) -> Vec<AccountId> {
+        self.apps.get(&app_id).map(|app| app.writers.to_vec()).unwrap_or_default()
+    }
+
+    /// Returns messages posted to the app with `post_message` in the order they were posted.
+    pub fn get_app_inbox(&self, app_id: AppId, from_index: u64, limit: u64) -> AppInboxResponse {
+        let limit = page_limit(limit, &self.config);
+        let inbox = self.app_inboxes.get(&app_id);
+        let num_messages = inbox.as_ref().map(|inbox| inbox.len()).unwrap_or(0);
+        let mut messages = Vec::new();
+        if let Some(inbox) = inbox {
+            let mut index = from_index;
+            while (messages.len() as u64) < limit && index < inbox.len() {
+                messages.push(inbox.get(index).unwrap());
+                index += 1;
+            }
+        }
+        AppInboxResponse { num_messages, messages }
+    }
+
+    /// Writes the value only if the current value equals `expected`, where `None` means the key is
+    /// not set. Returns whether the value was written.
+    pub fn master_compare_and_set(&mut self, app_id: AppId, key: Key, expected: Option<Value>, new_value: Value) -> bool {
+        self.assert_app_writer(&app_id);
+        if self.read_app_value(&app_id, &key) != expected {
+            return false;
+        }
+        self.set_app_values(&app_id, vec![(key, new_value)]);
+        true
+    }
+
+    /// Writes the value that is treated as absent after `ttl_ms` milliseconds. Expired values are
+    /// removed on later writes.
+    pub fn master_set_with_ttl(&mut self, app_id: AppId, key: Key, value: Value, ttl_ms: u64) {
+        self.assert_app_writer(&app_id);
+        assert!(ttl_ms > 0, "TTL should be positive");
+        self.set_app_values(&app_id, vec![(key.clone(), value)]);
+        let expires_at = env::block_timestamp() / 1000000 + ttl_ms;
+        self.app_value_expirations.insert(&app_key(&app_id, &key), &AppValueExpiration {
+            app_id,
+            key,
+            expires_at,
+        });
+    }
+
+    /// Writes raw bytes given as base64. Binary values are read with `get_base64`.
+    pub fn master_set_base64(&mut self, app_id: AppId, key: Key, value_base64: String) {
+        self.assert_app_writer(&app_id);
+        let value = base64::decode(&value_base64).unwrap_or_else(|_| env::panic(b"Can't decode the base64 value"));
+        self.set_app_values(&app_id, vec![(key, value)]);
+    }
+
+    /// Writes all entries in one call. If any write fails, none of them are applied.
+    pub fn master_set_batch(&mut self, app_id: AppId, entries: Vec<(Key, Value)>) {
+        self.assert_app_writer(&app_id);
+        self.set_app_values(&app_id, entries);
+    }
+
+    /// Removes all keys in one call. If any removal fails, none of them are applied.
+    pub fn master_remove_batch(&mut self, app_id: AppId, keys: Vec<Key>) {
+        self.assert_app_writer(&app_id);
+        self.remove_app_values(&app_id, keys);
+    }
+
+    /// Rejects new messages and edits that contain the word, ignoring case.
+    pub fn add_banned_word(&mut self, word: String) {
+        self.assert_owner();
+        verify_banned_word(&word);
+        self.banned_words.insert(&word.to_lowercase());
+    }
+
+    pub fn remove_banned_word(&mut self, word: String) {
+        self.assert_owner();
+        assert!(self.banned_words.remove(&word.to_lowercase()), "The word is not banned");
+    }
+
+    pub fn get_banned_words(&self) -> Vec<String> {
+        self.banned_words.to_vec()
+    }
+
+    /// Rejects all changes from the account in every channel. With `hide_messages` the messages the
+    /// account already posted are redacted in views.
+    pub fn ban_account(&mut self, account_id: AccountId, reason: String, hide_messages: bool) {
+        self.assert_owner();
+        verify_message_text(&reason, &self.config);
+        let ban = GlobalBan { reason, time: env::block_timestamp() / 1000000, hide_messages };
+        self.global_bans.insert(&account_id, &ban);
+    }
+
+    pub fn unban_account(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        assert!(self.global_bans.remove(&account_id).is_some(), "The account is not banned");
+    }
+
+    pub fn get_global_ban(&self, account_id: AccountId) -> Option<GlobalBan> {
+        self.global_bans.get(&account_id)
+    }
+
+    pub fn get_status(&self) -> StatusResponse {
+        self.assert_migrated();
+        self.status()
+    }
+
+    /// Returns the status of a public channel. Use the `ChannelStatus` request of `get` to read
+    /// private channels.
+    pub fn get_channel_status(&self, channel_id: ChannelId) -> ChannelStatusResponse {
+        self.assert_migrated();
+        self.channel_status(channel_id, None)
+    }
+
+    /// Returns a page of messages of a public channel. Use the `ChannelMessages` request of `get`
+    /// to read private channels or to include sender profiles.
+    pub fn get_channel_messages(&self, channel_id: ChannelId, from_index: u64, limit: u64) -> ChannelMessagesResponse {
+        self.assert_migrated();
+        self.channel_messages(channel_id, from_index, limit, None, false, None)
+    }
+
+    /// Returns the response of `get_status` serialized with Borsh and encoded as base64.
+    pub fn get_status_borsh(&self) -> String {
+        borsh_base64(&self.get_status())
+    }
+
+    /// Returns the response of `get_channel_status` serialized with Borsh and encoded as base64.
+    pub fn get_channel_status_borsh(&self, channel_id: ChannelId) -> String {
+        borsh_base64(&self.get_channel_status(channel_id))
+    }
+
+    /// Returns the response of `get_channel_messages` serialized with Borsh and encoded as base64.
+    pub fn get_channel_messages_borsh(&self, channel_id: ChannelId, from_index: u64, limit: u64) -> String {
+        borsh_base64(&self.get_channel_messages(channel_id, from_index, limit))
+    }
+
+    pub fn get(&self, app_id: AppId, key: Key) -> Option<Value> {
+        verify_app_id(&app_id, &self.config);
+        if app_id.as_bytes() == CHAT_APP_ID {
+            let request: GetRequest = serde_json::from_str(&key).expect("Can't parse key request");
+            self.assert_migrated();
+            self.get_chat(request)
+        } else {
+            self.read_app_value(&app_id, &key)
+        }
+    }
+
+    /// Returns the value of the app store key encoded as base64. Works for both text and binary values.
+    pub fn get_base64(&self, app_id: AppId, key: Key) -> Option<String> {
+        verify_app_id(&app_id, &self.config);
+        assert_ne!(app_id.as_bytes(), CHAT_APP_ID, "The chat app doesn't store values");
+        self.read_app_bytes(&app_id, &key).map(|bytes| base64::encode(&bytes))
+    }
+
+    /// Returns up to `limit` key/value pairs of the app store whose keys start with the prefix,
+    /// sorted by key. Only the first 1000 keys in the order of the `AppKeys` request are scanned, so
+    /// apps with more keys should page through `AppKeys` instead.
+    pub fn get_by_prefix(&self, app_id: AppId, prefix: String, limit: u64) -> Vec<(Key, Value)> {
+        verify_app_id(&app_id, &self.config);
+        let limit = page_limit(limit, &self.config);
+        let mut keys: Vec<Key> = match self.app_keys.get(&app_id) {
+            Some(app_keys) => {
+                app_keys.iter().take(MAX_PREFIX_SCAN_KEYS).filter(|key| key.starts_with(&prefix)).collect()
+            },
+            None => Vec::new(),
+        };
+        keys.sort();
+        keys.into_iter()
+            .filter_map(|key| self.read_app_value(&app_id, &key).map(|value| (key, value)))
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Called when receiving a message. The storage used by the message is paid from the storage
+    /// balance of the sender. The attached deposit is added to the storage balance first. Messages
+    /// to apps other than the chat app are appended to the inbox of the app.
+    pub fn post_message(&mut self, app_id: AppId, message: String) -> PostResult {
+        self.receive_message(env::predecessor_account_id(), app_id, message)
+    }
+
+    /// Called by a trusted proxy contract to post a message on behalf of the origin account. The
+    /// message is attributed to the origin account, and the attached deposit is added to its storage
+    /// balance.
+    pub fn proxy_post_message(&mut self, app_id: AppId, origin_account_id: AccountId, message: String) -> PostResult {
+        assert!(
+            self.trusted_proxies.contains(&env::predecessor_account_id()),
+            "Only trusted proxy contracts can post on behalf of other accounts"
+        );
+        self.receive_message(origin_account_id, app_id, message)
+    }
+
+    /// Posts a text message signed by its sender, so accounts without NEAR for gas can chat through a
+    /// relayer. The payload is a JSON `SignedPayload` signed with the ed25519 key the sender set with
+    /// `SetSigningKey`. The attached deposit is added to the storage balance of the sender.
+    pub fn post_signed_message(&mut self, payload: String, signature: String, public_key: String) -> PostResult {
+        let signed: SignedPayload = serde_json::from_str(&payload).expect("Can't parse the signed payload");
+        assert_eq!(signed.contract_id, env::current_account_id(), "The payload is signed for another contract");
+        let signing_key = self.signing_keys.get(&signed.sender_id).expect("The sender has no signing key");
+        assert_eq!(signing_key, public_key, "The public key is not the signing key of the sender");
+        verify_signature(payload.as_bytes(), &signature, &verifying_key(&public_key));
+        let last_nonce = self.signing_nonces.get(&signed.sender_id).unwrap_or(0);
+        assert!(signed.nonce > last_nonce, "The nonce should be greater than the last nonce of the sender");
+        self.signing_nonces.insert(&signed.sender_id, &signed.nonce);
+        let time = env::block_timestamp() / 1000000;
+
+        self.add_attached_deposit(&signed.sender_id);
+        let initial_storage_usage = env::storage_usage();
+        let incoming_message = IncomingMessage::ChatMessage {
+            channel_id: signed.channel_id,
+            body: MessageBody::Text { text: signed.text },
+            attachments: vec![],
+        };
+        let result = self.process_message(signed.sender_id.clone(), incoming_message, time);
+        self.charge_storage(&signed.sender_id, initial_storage_usage);
+        result
+    }
+
+    /// Called by a trusted peer deployment of this contract to mirror a message posted to it. The
+    /// message keeps its original sender and records where it was posted. The storage is paid from
+    /// the storage balance of the peer.
+    pub fn bridge_message(
+        &mut self,
+        channel_id: ChannelId,
+        sender_id: AccountId,
+        body: MessageBody,
+        attachments: Vec<Attachment>,
+        origin_channel_id: ChannelId,
+        origin_message_index: u64,
+    ) -> PostResult {
+        self.assert_not_paused();
+        let peer_id = env::predecessor_account_id();
+        assert!(self.trusted_peers.contains(&peer_id), "Only trusted peer contracts can bridge messages");
+        assert!(self.global_bans.get(&sender_id).is_none(), "The account is banned from the contract");
+        verify_message_body(&body, &sender_id, &self.config);
+        self.assert_no_banned_words(&body);
+        verify_attachments(&attachments, &self.config);
+        let time = env::block_timestamp() / 1000000;
+
+        self.add_attached_deposit(&peer_id);
+        let initial_storage_usage = env::storage_usage();
+        let mut channel = self.get_accessible_channel(channel_id, None);
+        channel.assert_can_post(&sender_id);
+        let mentions = body.mentions(&sender_id, &self.config);
+        let mut message = Message::new(sender_id, body, attachments, time);
+        message.bridged_from = Some(BridgedFrom {
+            contract_id: peer_id.clone(),
+            channel_id: origin_channel_id,
+            message_index: origin_message_index,
+        });
+        message.storage_payer_id = Some(peer_id.clone());
+        let message_index = self.post_to_channel(&mut channel, message, mentions);
+        self.charge_storage(&peer_id, initial_storage_usage);
+        PostResult { channel_id: channel.channel_id, message_index, time }
+    }
+
+    /// Adds the peer deployment of this contract that can bridge messages into this one.
+    pub fn add_trusted_peer(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.trusted_peers.insert(&account_id);
+    }
+
+    pub fn remove_trusted_peer(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        assert!(self.trusted_peers.remove(&account_id), "The account is not a trusted peer");
+    }
+
+    pub fn get_trusted_peers(&self) -> Vec<AccountId> {
+        self.trusted_peers.to_vec()
+    }
+
+    /// Adds the proxy contract that can post messages on behalf of other accounts.
+    pub fn add_trusted_proxy(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.trusted_proxies.insert(&account_id);
+    }
+
+    pub fn remove_trusted_proxy(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        assert!(self.trusted_proxies.remove(&account_id), "The account is not a trusted proxy");
+    }
+
+    pub fn get_trusted_proxies(&self) -> Vec<AccountId> {
+        self.trusted_proxies.to_vec()
+    }
+
+    /// Called when receiving multiple messages at once. Either all messages are processed or none.
+    pub fn post_messages(&mut self, app_id: AppId, messages: Vec<String>) -> Vec<PostResult> {
+        verify_app_id(&app_id, &self.config);
+        assert_eq!(app_id.as_bytes(), CHAT_APP_ID, "I only support chat messages");
+        assert!(messages.len() as u64 <= self.config.max_batch_size, "Too many messages in the batch");
+
+        let sender_id = env::predecessor_account_id();
+        let time = env::block_timestamp() / 1000000;
+
+        self.add_attached_deposit(&sender_id);
+        let initial_storage_usage = env::storage_usage();
+        let mut has_tip = false;
+        let results = messages
+            .into_iter()
+            .map(|message| match parse_incoming_message(&message) {
+                Ok(incoming_message) => {
+                    if let IncomingMessage::Tip { .. } = incoming_message {
+                        assert!(!has_tip, "Only one message in the batch can tip the attached deposit");
+                        has_tip = true;
+                    }
+                    self.process_message(sender_id.clone(), incoming_message, time)
+                },
+                Err(message_type) => reject_message(&sender_id, &message_type, time),
+            })
+            .collect();
+        self.charge_storage(&sender_id, initial_storage_usage);
+        results
+    }
+
+    /// Adds the attached deposit to the storage balance of the account or the predecessor. With
+    /// `registration_only` the deposit is refunded, since accounts don't need a minimum balance.
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        self.assert_not_paused();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let balance = self.storage_balances.get(&account_id).unwrap_or(0);
+        let deposit = env::attached_deposit();
+        if registration_only.unwrap_or(false) {
+            if deposit > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(deposit);
+            }
+            self.storage_balances.insert(&account_id, &balance);
+            return StorageBalance::new(balance);
+        }
+        self.storage_balances.insert(&account_id, &(balance + deposit));
+        StorageBalance::new(balance + deposit)
+    }
+
+    /// Withdraws the given amount or the whole storage balance of the predecessor. Requires exactly
+    /// 1 yoctoNEAR attached.
+    pub fn storage_withdraw(&mut self, amount: Option<String>) -> StorageBalance {
+        assert_eq!(env::attached_deposit(), 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+        let account_id = env::predecessor_account_id();
+        let balance = self.storage_balances.get(&account_id).expect("The account is not registered");
+        let amount = amount.map(|amount| amount.parse::<Balance>().expect("Invalid amount")).unwrap_or(balance);
+        assert!(amount <= balance, "The amount is greater than the available storage balance");
+        self.storage_balances.insert(&account_id, &(balance - amount));
+        Promise::new(account_id).transfer(amount + 1);
+        StorageBalance::new(balance - amount)
+    }
+
+    /// Called by a NEP-141 token contract when tokens are transferred to this contract. The tokens
+    /// are credited according to `msg`. Returns the amount of unused tokens.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: String, msg: String) -> String {
+        self.assert_not_paused();
+        let token_id = env::predecessor_account_id();
+        let amount: Balance = amount.parse().expect("Invalid amount");
+        let message: TokenTransferMessage = serde_json::from_str(&msg).expect("Can't parse the transfer message");
+        match message {
+            TokenTransferMessage::Tip { channel_id, message_index } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                let author_id = channel.tipped_message_author(message_index, &sender_id);
+                self.add_token_balance(&author_id, &token_id, amount);
+            },
+        }
+        "0".to_string()
+    }
+
+    /// Withdraws the given amount or the whole balance of the token received as tips. Requires
+    /// exactly 1 yoctoNEAR attached.
+    pub fn withdraw_tokens(&mut self, token_id: AccountId, amount: Option<String>) -> Promise {
+        assert_eq!(env::attached_deposit(), 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+        let account_id = env::predecessor_account_id();
+        let mut balances = self.token_balances.get(&account_id).expect("The account has no token balances");
+        let balance = balances.get(&token_id).expect("The account has no balance of the token");
+        let amount = amount.map(|amount| amount.parse::<Balance>().expect("Invalid amount")).unwrap_or(balance);
+        assert!(amount <= balance, "The amount is greater than the token balance");
+        balances.insert(&token_id, &(balance - amount));
+        self.token_balances.insert(&account_id, &balances);
+        Promise::new(token_id.clone())
+            .function_call(
+                b"ft_transfer".to_vec(),
+                serde_json::json!({ "receiver_id": account_id, "amount": amount.to_string() }).to_string().into_bytes(),
+                1,
+                GAS_FOR_FT_TRANSFER,
+            )
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"on_tokens_withdrawn".to_vec(),
+                serde_json::json!({ "account_id": account_id, "token_id": token_id, "amount": amount.to_string() })
+                    .to_string()
+                    .into_bytes(),
+                0,
+                GAS_FOR_WITHDRAW_CALLBACK,
+            ))
+    }
+
+    /// Callback for `withdraw_tokens`. Restores the balance if the transfer failed.
+    pub fn on_tokens_withdrawn(&mut self, account_id: AccountId, token_id: AccountId, amount: String) -> bool {
+        assert_self();
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => true,
+            _ => {
+                self.add_token_balance(&account_id, &token_id, amount.parse().unwrap());
+                false
+            },
+        }
+    }
+
+    /// Returns balances of the tokens the account received as tips.
+    pub fn get_token_balances(&self, account_id: AccountId) -> BTreeMap<AccountId, String> {
+        self.token_balances
+            .get(&account_id)
+            .map(|balances| balances.iter().map(|(token_id, balance)| (token_id, balance.to_string())).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_balances.get(&account_id).map(StorageBalance::new)
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds { min: "0".to_string(), max: None }
+    }
+
+    /// Describes the deployed contract, so clients can detect the supported requests and limits.
+    pub fn contract_metadata(&self) -> ContractMetadata<'_> {
+        ContractMetadata {
+            version: env!("CARGO_PKG_VERSION"),
+            standard: EVENT_STANDARD,
+            standard_version: EVENT_STANDARD_VERSION,
+            get_requests: GET_REQUEST_KINDS,
+            incoming_messages: INCOMING_MESSAGE_KINDS,
+            config: &self.config,
+        }
+    }
+}
+
+impl MetanearChat {
+    /// Creates the state with no channels.
+    fn empty(owner_id: AccountId) -> Self {
+        Self {
+            owner_id,
+            paused: false,
+            channel_ids: Vector::new(b"u".to_vec()),
+            legacy_channels: Map::new(b"c".to_vec()),
+            total_num_messages: 0,
+            num_groups: 0,
+            num_crossposts: 0,
+            analytics: Analytics::default(),
+            reactions: Map::new(b"e".to_vec()),
+            config: Config::default(),
+            mentions: Map::new(b"@".to_vec()),
+            read_markers: Map::new(b"l".to_vec()),
+            invites: Map::new(b"d".to_vec()),
+            account_channels: Map::new(b"b".to_vec()),
+            tag_channels: Map::new(b"k".to_vec()),
+            profiles: Map::new(b"z".to_vec()),
+            last_active: Map::new(b"L".to_vec()),
+            blocked_accounts: Map::new(b"B".to_vec()),
+            storage_balances: Map::new(b"S".to_vec()),
+            post_windows: Map::new(b"R".to_vec()),
+            message_deposits: Map::new(b"D".to_vec()),
+            banned_words: Set::new(b"W".to_vec()),
+            global_bans: Map::new(b"G".to_vec()),
+            reputations: Map::new(b"P".to_vec()),
+            top_posters: Leaderboard::default(),
+            tips: Map::new(b"T".to_vec()),
+            token_balances: Map::new(b"F".to_vec()),
+            tips_received: Map::new(b"Y".to_vec()),
+            top_tipped: Leaderboard::default(),
+            treasury: Treasury::default(),
+            app_keys: Map::new(b"A".to_vec()),
+            app_value_expirations: Map::new(b"O".to_vec()),
+            apps: Map::new(b"M".to_vec()),
+            app_inboxes: Map::new(b"7".to_vec()),
+            trusted_proxies: Set::new(b"H".to_vec()),
+            trusted_peers: Set::new(b"2".to_vec()),
+            encryption_keys: Map::new(b"U".to_vec()),
+            signing_keys: Map::new(b"#".to_vec()),
+            signing_nonces: Map::new(b"$".to_vec()),
+            poll_votes: Map::new(b"V".to_vec()),
+            poll_voters: Map::new(b"Z".to_vec()),
+            custom_emoji: Map::new(b"0".to_vec()),
+            typing: Map::new(b"1".to_vec()),
+            account_reactions: Map::new(b"9".to_vec()),
+            mention_receivers: Map::new(b"!".to_vec()),
+            posted_channel_ids: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the app store key unless it expired.
+    fn read_app_bytes(&self, app_id: &AppId, key: &Key) -> Option<Vec<u8>> {
+        let storage_key = app_key(app_id, key);
+        if let Some(expiration) = self.app_value_expirations.get(&storage_key) {
+            if expiration.expires_at <= env::block_timestamp() / 1000000 {
+                return None;
+            }
+        }
+        env::storage_read(&storage_key)
+    }
+
+    fn read_app_value(&self, app_id: &AppId, key: &Key) -> Option<Value> {
+        self.read_app_bytes(app_id, key).map(|bytes| {
+            String::from_utf8(bytes).unwrap_or_else(|_| env::panic(b"The value is binary, use `get_base64`"))
+        })
+    }
+
+    /// Removes up to `APP_VALUE_SWEEP_BATCH_SIZE` expired app store values.
+    fn sweep_expired_app_values(&mut self) {
+        let time = env::block_timestamp() / 1000000;
+        let mut index = 0;
+        let mut num_checked = 0;
+        while num_checked < APP_VALUE_SWEEP_BATCH_SIZE && index < self.app_value_expirations.len() {
+            let expiration = self.app_value_expirations.values_as_vector().get(index).unwrap();
+            num_checked += 1;
+            if expiration.expires_at <= time {
+                // Removing the expiration moves the last one to this index.
+                self.remove_app_values(&expiration.app_id, vec![expiration.key]);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    fn set_app_values<V: AsRef<[u8]>>(&mut self, app_id: &AppId, entries: Vec<(Key, V)>) {
+        self.sweep_expired_app_values();
+        let mut keys = self.app_keys.get(app_id).unwrap_or_else(|| Set::new(app_keys_key(app_id)));
+        for (key, value) in entries {
+            let storage_key = app_key(app_id, &key);
+            env::storage_write(&storage_key, value.as_ref());
+            self.app_value_expirations.remove(&storage_key);
+            keys.insert(&key);
+        }
+        self.app_keys.insert(app_id, &keys);
+    }
+
+    fn remove_app_values(&mut self, app_id: &AppId, keys: Vec<Key>) {
+        let mut app_keys = self.app_keys.get(app_id);
+        for key in keys {
+            let storage_key = app_key(app_id, &key);
+            env::storage_remove(&storage_key);
+            self.app_value_expirations.remove(&storage_key);
+            if let Some(app_keys) = app_keys.as_mut() {
+                app_keys.remove(&key);
+            }
+        }
+        if let Some(app_keys) = app_keys {
+            self.app_keys.insert(app_id, &app_keys);
+        }
+    }
+
+    pub fn process_message(&mut self, sender_id: AccountId, incoming_message: IncomingMessage, time: u64) -> PostResult {
+        self.assert_not_paused();
+        assert!(self.global_bans.get(&sender_id).is_none(), "The account is banned from the contract");
+        self.last_active.insert(&sender_id, &time);
+        match incoming_message {
+            IncomingMessage::ChatMessage { channel_id, body, attachments } => {
+                verify_message_body(&body, &sender_id, &self.config);
+                self.assert_no_banned_words(&body);
+                verify_attachments(&attachments, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_can_post(&sender_id);
+                let mentions = body.mentions(&sender_id, &self.config);
+                let message = Message::new(sender_id, body, attachments, time);
+                let message_index = self.post_to_channel(&mut channel, message, mentions);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::ReplyMessage { channel_id, reply_to, body, attachments } => {
+                verify_message_body(&body, &sender_id, &self.config);
+                self.assert_no_banned_words(&body);
+                verify_attachments(&attachments, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_can_post(&sender_id);
+                let mentions = body.mentions(&sender_id, &self.config);
+                let mut message = Message::new(sender_id, body, attachments, time);
+                message.reply_to = Some(reply_to);
+                let message_index = self.post_to_channel(&mut channel, message, mentions);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::EditMessage { channel_id, message_index, new_body } => {
+                verify_message_body(&new_body, &sender_id, &self.config);
+                self.assert_no_banned_words(&new_body);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.edit_message(message_index, &sender_id, new_body, time);
+                self.save_channel(&channel);
+                emit_event(ChatEvent::MessageEdited(vec![MessageEventData {
+                    channel_id: &channel.channel_id,
+                    message_index,
+                    sender_id: &sender_id,
+                }]));
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::DeleteMessage { channel_id, message_index } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                let freed_storage = channel.delete_message(message_index, &sender_id);
+                self.refund_storage(freed_storage);
+                emit_event(ChatEvent::MessageDeleted(vec![MessageEventData {
+                    channel_id: &channel.channel_id,
+                    message_index,
+                    sender_id: &sender_id,
+                }]));
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::ModDelete { channel_id, message_index, reason } => {
+                verify_message_text(&reason, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                let freed_storage = channel.moderator_delete_message(message_index, &sender_id, reason, time);
+                self.refund_storage(freed_storage);
+                self.forfeit_message_deposit(&channel, message_index);
+                self.save_channel(&channel);
+                emit_event(ChatEvent::MessageDeleted(vec![MessageEventData {
+                    channel_id: &channel.channel_id,
+                    message_index,
+                    sender_id: &sender_id,
+                }]));
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::BanAccount { channel_id, account_id, reason } => {
+                verify_message_text(&reason, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                assert!(channel.is_moderator(&sender_id), "Only moderators can ban accounts");
+                assert!(!channel.is_owner(&account_id), "Can't ban the channel owner");
+                channel.bans.insert(&account_id, &Ban { moderator_id: sender_id, reason, time });
+                if channel.is_member(&account_id) {
+                    self.remove_member(&mut channel, &account_id);
+                }
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::UnbanAccount { channel_id, account_id } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                assert!(channel.is_moderator(&sender_id), "Only moderators can unban accounts");
+                assert!(channel.bans.remove(&account_id).is_some(), "The account is not banned");
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::Report { channel_id, message_index, reason } => {
+                verify_message_text(&reason, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.report_message(message_index, Report { reporter_id: sender_id, reason, time });
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::ResolveReport { channel_id, message_index, remove } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                assert!(channel.is_moderator(&sender_id), "Only moderators can resolve reports");
+                assert!(channel.reports.get(&message_index).is_some(), "The message has no open reports");
+                if remove {
+                    let reported_id = channel.message(message_index).unwrap().sender_id;
+                    self.update_reputation(&reported_id, |reputation| reputation.num_reports += 1);
+                    let reason = REPORTED_MESSAGE_REMOVAL_REASON.to_string();
+                    let freed_storage = channel.moderator_delete_message(message_index, &sender_id, reason, time);
+                    self.refund_storage(freed_storage);
+                    self.forfeit_message_deposit(&channel, message_index);
+                    emit_event(ChatEvent::MessageDeleted(vec![MessageEventData {
+                        channel_id: &channel.channel_id,
+                        message_index,
+                        sender_id: &sender_id,
+                    }]));
+                } else {
+                    channel.restore_message(message_index);
+                }
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::SetReportThreshold { channel_id, report_threshold } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.report_threshold = report_threshold;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetMinReputation { channel_id, min_reputation } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.min_reputation = min_reputation;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::MuteAccount { channel_id, account_id, duration_ms } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                assert!(channel.is_moderator(&sender_id), "Only moderators can mute accounts");
+                assert!(!channel.is_moderator(&account_id), "Can't mute a moderator");
+                channel.mutes.insert(&account_id, &(time + duration_ms));
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetArchived { channel_id, archived } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.archived = archived;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetAnnouncement { channel_id, announcement } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.announcement = announcement;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetSlowMode { channel_id, slow_mode_ms } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.slow_mode_ms = slow_mode_ms;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetRetention { channel_id, retention } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.retention = retention;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetSubscription { channel_id, subscription } => {
+                if let Some(subscription) = &subscription {
+                    verify_subscription(subscription);
+                }
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.subscription = subscription;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetGate { channel_id, gate } => {
+                if let Some(gate) = &gate {
+                    verify_gate(gate);
+                }
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.gate = gate;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetChannelMeta { channel_id, topic, description } => {
+                verify_topic(&topic);
+                verify_message_text(&description, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.topic = topic;
+                channel.description = description;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetReceivers { channel_id, receivers } => {
+                verify_receivers(&receivers, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.receivers = receivers;
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetTags { channel_id, tags } => {
+                verify_tags(&tags, &self.config);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                self.set_tags(&mut channel, tags);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetCustomEmoji { channel_id, name, cid } => {
+                verify_custom_emoji_name(&name);
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                let mut custom_emoji = self.custom_emoji.get(&channel.channel_id).unwrap_or_default();
+                match cid {
+                    Some(cid) => {
+                        verify_cid(&cid);
+                        custom_emoji.insert(name, cid);
+                        assert!(
+                            custom_emoji.len() as u64 <= self.config.max_custom_emoji,
+                            "Too many custom emoji in the channel"
+                        );
+                    }
+                    None => {
+                        assert!(custom_emoji.remove(&name).is_some(), "Custom emoji doesn't exist");
+                    }
+                }
+                if custom_emoji.is_empty() {
+                    self.custom_emoji.remove(&channel.channel_id);
+                } else {
+                    self.custom_emoji.insert(&channel.channel_id, &custom_emoji);
+                }
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetChannelKind { channel_id, kind } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                channel.kind = kind;
+                if channel.kind != ChannelKind::Public {
+                    self.analytics.forget_channel(&channel.channel_id);
+                }
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::AddMember { channel_id, account_id } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                if channel.kind == ChannelKind::Group {
+                    assert!(channel.members.len() < self.config.max_group_size, "The group is full");
+                }
+                self.add_member(&mut channel, &account_id, time);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::RemoveMember { channel_id, account_id } => {
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_owner(&sender_id);
+                self.remove_member(&mut channel, &account_id);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::Invite { channel_id, account_id } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                assert!(channel.kind == ChannelKind::Private, "Only private channels require invites");
+                assert!(!channel.can_access(&account_id), "Already a member");
+                let channel_hash = env::sha256(channel.channel_id.as_bytes());
+                let mut invites = self.invites.get(&account_id).unwrap_or_else(|| Map::new(invites_key(&account_id)));
+                invites.insert(&channel_hash, &Invite {
+                    channel_id: channel.channel_id.clone(),
+                    inviter_id: sender_id,
+                    time,
+                });
+                self.invites.insert(&account_id, &invites);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::AcceptInvite { channel_id } => {
+                let mut channel = self.get_channel(channel_id);
+                let channel_hash = env::sha256(channel.channel_id.as_bytes());
+                let mut invites = self.invites.get(&sender_id).expect("No pending invites");
+                invites.remove(&channel_hash).expect("No pending invite to the channel");
+                if invites.len() > 0 {
+                    self.invites.insert(&sender_id, &invites);
+                } else {
+                    self.invites.remove(&sender_id);
+                }
+                self.add_member(&mut channel, &sender_id, time);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::JoinChannel { channel_id } => {
+                verify_unreserved_channel_id(&channel_id);
+                let mut channel = self.read_channel(&channel_id).expect("Channel doesn't exist");
+                assert!(channel.gate.is_none(), "Gated channels require `join_gated_channel`");
+                assert!(!channel.is_banned(&sender_id), "The account is banned from the channel");
+                if let Some(subscription) = &channel.subscription {
+                    let price: Balance = subscription.price.parse().unwrap();
+                    let starts_at = channel.subscriptions.get(&sender_id).unwrap_or(0).max(time);
+                    let fee_bps = self.config.subscription_fee_bps;
+                    self.pay_from_storage_balance(&sender_id, &channel.owner_id, price, fee_bps);
+                    channel.subscriptions.insert(&sender_id, &(starts_at + subscription.period_ms));
+                } else {
+                    assert!(channel.kind == ChannelKind::Public, "Private channels require an invite");
+                    assert!(!channel.is_member(&sender_id), "Already a member");
+                }
+                self.add_member(&mut channel, &sender_id, time);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::LeaveChannel { channel_id } => {
+                let mut channel = self.get_channel(channel_id);
+                self.remove_member(&mut channel, &sender_id);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::DirectMessage { recipient_id, body, attachments } => {
+                verify_message_body(&body, &sender_id, &self.config);
+                self.assert_no_banned_words(&body);
+                verify_attachments(&attachments, &self.config);
+                assert_ne!(sender_id, recipient_id, "Can't send a direct message to yourself");
+                assert!(!self.is_blocked(&recipient_id, &sender_id), "The recipient blocked the sender");
+                let mut channel = self.get_or_new_reserved_channel(direct_channel_id(&sender_id, &recipient_id));
+                if channel.kind != ChannelKind::Direct {
+                    channel.kind = ChannelKind::Direct;
+                    self.add_member(&mut channel, &sender_id, time);
+                    self.add_member(&mut channel, &recipient_id, time);
+                }
+                let mentions = body.mentions(&sender_id, &self.config);
+                let message = Message::new(sender_id, body, attachments, time);
+                let message_index = self.post_to_channel(&mut channel, message, mentions);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::CreateGroup { members, title } => {
+                verify_topic(&title);
+                let channel_id = format!("{}{}", GROUP_CHANNEL_PREFIX, self.num_groups);
+                self.num_groups += 1;
+                assert!(self.read_channel(&channel_id).is_none(), "The group already exists");
+                let mut channel = self.get_or_new_reserved_channel(channel_id);
+                channel.owner_id = sender_id.clone();
+                channel.kind = ChannelKind::Group;
+                channel.topic = title;
+                self.add_member(&mut channel, &sender_id, time);
+                for account_id in members {
+                    self.add_member(&mut channel, &account_id, time);
+                }
+                assert!(channel.members.len() <= self.config.max_group_size, "The group is full");
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index: 0, time }
+            },
+            IncomingMessage::Forward { from_channel_id, message_index, to_channel_id } => {
+                let from_channel = self.get_accessible_channel(from_channel_id, Some(&sender_id));
+                let original = from_channel.message(message_index).expect("Message doesn't exist");
+                assert!(!original.deleted, "The message is deleted");
+                assert!(!self.is_redacted(&original, Some(&sender_id)), "The message is hidden");
+                if let MessageBody::System { .. } = original.body {
+                    env::panic(b"System messages can't be forwarded");
+                }
+                verify_message_body(&original.body, &sender_id, &self.config);
+                self.assert_no_banned_words(&original.body);
+                verify_attachments(&original.attachments, &self.config);
+                let mut channel = self.get_accessible_channel(to_channel_id, Some(&sender_id));
+                channel.assert_can_post(&sender_id);
+                let mut message = Message::new(sender_id, original.body, original.attachments, time);
+                message.forwarded_from = Some(ForwardedFrom {
+                    channel_id: from_channel.channel_id,
+                    message_index,
+                    sender_id: original.sender_id,
+                    time: original.time,
+                });
+                let message_index = self.post_to_channel(&mut channel, message, vec![]);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::CrossPost { channel_ids, body, attachments } => {
+                assert!(!channel_ids.is_empty(), "No channels to post to");
+                assert!(
+                    channel_ids.len() as u64 <= self.config.max_crosspost_channels,
+                    "Too many channels to cross-post to"
+                );
+                verify_message_body(&body, &sender_id, &self.config);
+                self.assert_no_banned_words(&body);
+                verify_attachments(&attachments, &self.config);
+                let crosspost_id = self.num_crossposts;
+                self.num_crossposts += 1;
+                let mentions = body.mentions(&sender_id, &self.config);
+                let mut first_result = None;
+                for (index, channel_id) in channel_ids.iter().enumerate() {
+                    assert!(!channel_ids[..index].contains(channel_id), "Duplicate channel");
+                    let mut channel = self.get_accessible_channel(channel_id.clone(), Some(&sender_id));
+                    channel.assert_can_post(&sender_id);
+                    let mut message = Message::new(sender_id.clone(), body.clone(), attachments.clone(), time);
+                    message.crosspost_id = Some(crosspost_id);
+                    let message_index = self.post_to_channel(&mut channel, message, mentions.clone());
+                    first_result.get_or_insert(PostResult { channel_id: channel.channel_id, message_index, time });
+                }
+                first_result.unwrap()
+            },
+            IncomingMessage::Block { account_id } => {
+                assert_ne!(sender_id, account_id, "Can't block yourself");
+                let mut blocked = self
+                    .blocked_accounts
+                    .get(&sender_id)
+                    .unwrap_or_else(|| Set::new(blocked_accounts_key(&sender_id)));
+                assert!(!blocked.contains(&account_id), "The account is already blocked");
+                blocked.insert(&account_id);
+                self.blocked_accounts.insert(&sender_id, &blocked);
+                PostResult { channel_id: String::new(), message_index: 0, time }
+            },
+            IncomingMessage::Unblock { account_id } => {
+                let mut blocked = self.blocked_accounts.get(&sender_id).expect("The account is not blocked");
+                assert!(blocked.remove(&account_id), "The account is not blocked");
+                if blocked.len() > 0 {
+                    self.blocked_accounts.insert(&sender_id, &blocked);
+                } else {
+                    self.blocked_accounts.remove(&sender_id);
+                }
+                PostResult { channel_id: String::new(), message_index: 0, time }
+            },
+            IncomingMessage::Heartbeat {} => PostResult { channel_id: String::new(), message_index: 0, time },
+            IncomingMessage::Typing { channel_id } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_can_post(&sender_id);
+                let mut typing: Vec<TypingIndicator> = self
+                    .typing
+                    .get(&channel.channel_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|indicator| indicator.expires_at > time && indicator.account_id != sender_id)
+                    .collect();
+                typing.push(TypingIndicator { account_id: sender_id, expires_at: time + TYPING_INDICATOR_MS });
+                self.typing.insert(&channel.channel_id, &typing);
+                PostResult { channel_id: channel.channel_id, message_index: channel.messages.len(), time }
+            },
+            IncomingMessage::SetProfile { display_name, avatar_url, bio } => {
+                let profile = Profile { display_name, avatar_url, bio };
+                verify_profile(&profile);
+                self.profiles.insert(&sender_id, &profile);
+                PostResult { channel_id: String::new(), message_index: 0, time }
+            },
+            IncomingMessage::SetEncryptionKey { public_key } => {
+                match public_key {
+                    Some(public_key) => {
+                        verify_encryption_key(&public_key);
+                        self.encryption_keys.insert(&sender_id, &public_key);
+                    }
+                    None => {
+                        self.encryption_keys.remove(&sender_id);
+                    }
+                }
+                PostResult { channel_id: String::new(), message_index: 0, time }
+            },
+            IncomingMessage::SetSigningKey { public_key } => {
+                match public_key {
+                    Some(public_key) => {
+                        verifying_key(&public_key);
+                        self.signing_keys.insert(&sender_id, &public_key);
+                    }
+                    None => {
+                        self.signing_keys.remove(&sender_id);
+                    }
+                }
+                PostResult { channel_id: String::new(), message_index: 0, time }
+            },
+            IncomingMessage::SetMentionReceiver { receiver_id } => {
+                match receiver_id {
+                    Some(receiver_id) => {
+                        verify_receiver_id(&receiver_id);
+                        self.mention_receivers.insert(&sender_id, &receiver_id);
+                    }
+                    None => {
+                        self.mention_receivers.remove(&sender_id);
+                    }
+                }
+                PostResult { channel_id: String::new(), message_index: 0, time }
+            },
+            IncomingMessage::MarkRead { channel_id, up_to_index } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                assert!(up_to_index < channel.messages.len(), "Message doesn't exist");
+                let channel_hash = env::sha256(channel.channel_id.as_bytes());
+                self.read_markers.insert(&read_marker_key(&sender_id, &channel_hash), &up_to_index);
+                PostResult { channel_id: channel.channel_id, message_index: up_to_index, time }
+            },
+            IncomingMessage::React { channel_id, message_index, emoji } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                self.add_reaction(&channel, message_index, &sender_id, emoji);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::Unreact { channel_id, message_index, emoji } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                self.remove_reaction(&channel, message_index, &sender_id, emoji);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::CreatePoll { channel_id, question, options, closes_at, weight_token_id } => {
+                verify_poll(&question, &options, closes_at, time);
+                let body = MessageBody::Poll { question, options, closes_at, weight_token_id };
+                self.assert_no_banned_words(&body);
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                channel.assert_can_post(&sender_id);
+                let message = Message::new(sender_id, body, Vec::new(), time);
+                let message_index = self.post_to_channel(&mut channel, message, Vec::new());
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::Vote { channel_id, message_index, option } => {
+                let channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                self.vote(&channel, message_index, &sender_id, option, None, time);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+            IncomingMessage::Tip { channel_id, message_index } => {
+                // The attached deposit was added to the storage balance of the sender, so it's paid
+                // from there.
+                let amount = env::attached_deposit();
+                let mut channel = self.get_accessible_channel(channel_id, Some(&sender_id));
+                self.tip_message(&mut channel, message_index, &sender_id, amount);
+                self.save_channel(&channel);
+                PostResult { channel_id: channel.channel_id, message_index, time }
+            },
+        }
+    }
+
+    /// Adds the attached deposit to the storage balance of the sender. The contract account doesn't
+    /// pay for storage, so its deposit is refunded.
+    fn add_attached_deposit(&mut self, sender_id: &AccountId) {
+        let attached_deposit = env::attached_deposit();
+        if attached_deposit == 0 {
+            return;
+        }
+        if sender_id == &env::current_account_id() {
+            Promise::new(sender_id.clone()).transfer(attached_deposit);
+            return;
+        }
+        let balance = self.storage_balances.get(sender_id).unwrap_or(0);
+        self.storage_balances.insert(sender_id, &(balance + attached_deposit));
+    }
+
+    /// Charges the sender for the storage used since `initial_storage_usage`, then prunes the channels
+    /// posted to in the meantime. The storage freed by pruning is returned to the accounts that paid
+    /// for it instead of reducing the charge.
+    fn charge_storage(&mut self, sender_id: &AccountId, initial_storage_usage: u64) {
+        if sender_id != &env::current_account_id() {
+            let storage_cost =
+                Balance::from(env::storage_usage().saturating_sub(initial_storage_usage)) * STORAGE_PRICE_PER_BYTE;
+            let balance = self.storage_balances.get(sender_id).unwrap_or(0);
+            assert!(balance >= storage_cost, "Not enough storage balance to cover the storage of the message");
+            if balance > 0 {
+                self.storage_balances.insert(sender_id, &(balance - storage_cost));
+            }
+        }
+        self.prune_posted_channels();
+    }
+
+    /// Prunes the channels that received messages during the current call.
+    fn prune_posted_channels(&mut self) {
+        let time = env::block_timestamp() / 1000000;
+        for channel_id in std::mem::take(&mut self.posted_channel_ids) {
+            let mut channel = self.read_channel(&channel_id).unwrap();
+            let pruned = channel.prune(time, PRUNE_BATCH_SIZE);
+            if pruned.is_empty() {
+                continue;
+            }
+            self.save_channel(&channel);
+            for freed_storage in pruned {
+                self.refund_storage(freed_storage);
+            }
+        }
+    }
+
+    /// Returns the channel. A channel that doesn't exist yet is owned by the contract account.
+    pub fn get_channel(&self, channel_id: ChannelId) -> Channel {
+        self.get_or_new_channel(channel_id, None)
+    }
+
+    /// Returns the channel or a new channel owned by the given account if the channel doesn't exist.
+    fn get_or_new_channel(&self, channel_id: ChannelId, owner_id: Option<&AccountId>) -> Channel {
+        verify_channel_id(&channel_id, &self.config);
+        self.assert_migrated();
+        self.read_channel(&channel_id).unwrap_or_else(|| {
+            verify_unreserved_channel_id(&channel_id);
+            Channel::new(channel_id, owner_id.cloned().unwrap_or_else(env::current_account_id))
+        })
+    }
+
+    /// Returns the channel with a reserved ID or a new channel owned by the contract account. Only
+    /// direct messages and group conversations create such channels.
+    fn get_or_new_reserved_channel(&self, channel_id: ChannelId) -> Channel {
+        self.assert_migrated();
+        self.read_channel(&channel_id)
+            .unwrap_or_else(|| Channel::new(channel_id, env::current_account_id()))
+    }
+
+    /// Returns the channel, panicking if the account can't access it. Private channels require an
+    /// account that is a member. A channel that doesn't exist yet is owned by the account.
+    pub fn get_accessible_channel(&self, channel_id: ChannelId, account_id: Option<&AccountId>) -> Channel {
+        let channel = self.get_or_new_channel(channel_id, account_id);
+        assert!(
+            account_id.map(|account_id| channel.can_access(account_id)).unwrap_or(channel.kind == ChannelKind::Public),
+            "No access to the private channel"
+        );
+        channel
+    }
+
+    pub fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only the contract owner can do this");
+    }
+
+    /// Asserts that the predecessor is the contract owner, the app owner or one of the app writers.
+    pub fn assert_app_writer(&self, app_id: &AppId) {
+        let predecessor_id = env::predecessor_account_id();
+        if predecessor_id == self.owner_id {
+            return;
+        }
+        let app = self.apps.get(app_id);
+        assert!(
+            app.map(|app| app.owner_id == predecessor_id || app.writers.contains(&predecessor_id)).unwrap_or(false),
+            "Only the app owner or writers can do this"
+        );
+    }
+
+    fn receive_message(&mut self, sender_id: AccountId, app_id: AppId, message: String) -> PostResult {
+        verify_app_id(&app_id, &self.config);
+
+        let time = env::block_timestamp() / 1000000;
+
+        self.add_attached_deposit(&sender_id);
+        let initial_storage_usage = env::storage_usage();
+        let result = if app_id.as_bytes() == CHAT_APP_ID {
+            match parse_incoming_message(&message) {
+                Ok(incoming_message) => self.process_message(sender_id.clone(), incoming_message, time),
+                Err(message_type) => reject_message(&sender_id, &message_type, time),
+            }
+        } else {
+            self.deliver_to_app_inbox(&app_id, sender_id.clone(), message, time)
+        };
+        self.charge_storage(&sender_id, initial_storage_usage);
+        result
+    }
+
+    /// Appends a message for an app other than the chat app to the inbox of the app. The result
+    /// doesn't target a channel and holds the index of the message in the inbox.
+    fn deliver_to_app_inbox(
+        &mut self,
+        app_id: &AppId,
+        sender_id: AccountId,
+        message: String,
+        time: u64,
+    ) -> PostResult {
+        self.assert_not_paused();
+        assert!(self.global_bans.get(&sender_id).is_none(), "The account is banned from the contract");
+        assert!(message.len() as u64 <= self.config.max_message_len, "Message is too long");
+        let mut inbox = self.app_inboxes.get(app_id).unwrap_or_else(|| Vector::new(app_inbox_key(app_id)));
+        let message_index = inbox.len();
+        inbox.push(&InboxMessage { sender_id, message, time });
+        self.app_inboxes.insert(app_id, &inbox);
+        PostResult { channel_id: String::new(), message_index, time }
+    }
+
+    fn app_view(&self, app_id: AppId, app: App) -> AppView {
+        let num_keys = self.app_keys.get(&app_id).map(|keys| keys.len()).unwrap_or(0);
+        AppView {
+            app_id,
+            owner_id: app.owner_id,
+            metadata: app.metadata,
+            num_keys,
+        }
+    }
+
+    /// Returns the app if the predecessor is the contract owner or the app owner.
+    fn get_app_as_owner(&self, app_id: &AppId) -> App {
+        let app = self.apps.get(app_id).expect("App doesn't exist");
+        let predecessor_id = env::predecessor_account_id();
+        assert!(
+            predecessor_id == self.owner_id || predecessor_id == app.owner_id,
+            "Only the app owner can do this"
+        );
+        app
+    }
+
+    fn status(&self) -> StatusResponse {
+        StatusResponse { num_channels: self.channel_ids.len(), total_num_messages: self.total_num_messages }
+    }
+
+    fn channel_status(&self, channel_id: ChannelId, reader_id: Option<&AccountId>) -> ChannelStatusResponse {
+        let channel = self.get_accessible_channel(channel_id, reader_id);
+        let chain_head = channel.chain_head().map(|head| hex_encode(&head));
+        ChannelStatusResponse {
+            owner_id: channel.owner_id,
+            kind: channel.kind,
+            announcement: channel.announcement,
+            num_members: channel.members.len(),
+            num_messages: channel.messages.len(),
+            num_pruned_messages: channel.num_pruned_messages,
+            retention: channel.retention,
+            gate: channel.gate,
+            subscription: channel.subscription,
+            topic: channel.topic,
+            description: channel.description,
+            tags: channel.tags,
+            receivers: channel.receivers,
+            chain_head,
+            created_at: channel.created_at,
+        }
+    }
+
+    fn channel_messages(
+        &self,
+        channel_id: ChannelId,
+        from_index: u64,
+        limit: u64,
+        reader_id: Option<&AccountId>,
+        include_profiles: bool,
+        viewer_id: Option<&AccountId>,
+    ) -> ChannelMessagesResponse {
+        let limit = page_limit(limit, &self.config);
+        let channel = self.get_accessible_channel(channel_id, reader_id);
+        let mut messages = Vec::new();
+        let mut index = from_index;
+        while (messages.len() as u64) < limit && index < channel.messages.len() {
+            messages.push(self.message_view(&channel, index, viewer_id));
+            index += 1;
+        }
+        let profiles = if include_profiles { Some(self.sender_profiles(&messages)) } else { None };
+        let total = channel.messages.len();
+        ChannelMessagesResponse { messages, total, has_more: index < total, profiles }
+    }
+
+    /// Evaluates a request to the chat app of `get`. Responses are JSON strings.
+    fn get_chat(&self, request: GetRequest) -> Option<Value> {
+        match request {
+            GetRequest::Batch(requests) => {
+                assert!(requests.len() as u64 <= self.config.max_page_size, "Too many requests in the batch");
+                let responses: Vec<String> = requests
+                    .into_iter()
+                    .map(|request| {
+                        if let GetRequest::Batch(_) = request {
+                            env::panic(b"Batches can't be nested");
+                        }
+                        self.get_chat(request).unwrap_or_else(|| "null".to_string())
+                    })
+                    .collect();
+                Some(format!("[{}]", responses.join(",")))
+            },
+            GetRequest::Status {} => Some(serde_json::to_string(&self.status()).unwrap()),
+            GetRequest::Channels { from_index, limit, skip_archived } => {
+                let limit = page_limit(limit, &self.config);
+                let mut channels = Vec::new();
+                let mut index = from_index;
+                while (channels.len() as u64) < limit && index < self.channel_ids.len() {
+                    let channel = self.read_channel(&self.channel_ids.get(index).unwrap()).unwrap();
+                    if !(skip_archived && channel.archived) {
+                        channels.push(channel.into_info());
+                    }
+                    index += 1;
+                }
+                Some(serde_json::to_string(&ChannelsResponse {
+                    num_channels: self.channel_ids.len(),
+                    channels,
+                }).unwrap())
+            },
+            GetRequest::ChannelsByTag { tag, from_index, limit } => {
+                let limit = page_limit(limit, &self.config);
+                let tag_channels = self.tag_channels.get(&tag);
+                let num_channels = tag_channels.as_ref().map(|channels| channels.len()).unwrap_or(0);
+                let mut channels = Vec::new();
+                if let Some(tag_channels) = tag_channels {
+                    let channel_ids = tag_channels.as_vector();
+                    let mut index = from_index;
+                    while (channels.len() as u64) < limit && index < channel_ids.len() {
+                        let channel = self.get_channel(channel_ids.get(index).unwrap());
+                        channels.push(channel.into_info());
+                        index += 1;
+                    }
+                }
+                Some(serde_json::to_string(&ChannelsResponse {
+                    num_channels,
+                    channels,
+                }).unwrap())
+            },
+            GetRequest::TrendingChannels { limit, window_ms } => {
+                let limit = page_limit(limit, &self.config);
+                let since = (env::block_timestamp() / 1000000).saturating_sub(window_ms);
+                let mut channels: Vec<TrendingChannel> = self
+                    .analytics
+                    .active_channel_ids
+                    .iter()
+                    .map(|channel_id| self.get_channel(channel_id.clone()))
+                    .filter(|channel| channel.kind == ChannelKind::Public && !channel.archived)
+                    .filter(|channel| channel.last_message_time >= since && !channel.messages.is_empty())
+                    .map(|channel| TrendingChannel {
+                        num_recent_messages: channel.num_messages_since(since),
+                        last_message_time: channel.last_message_time,
+                        channel_id: channel.channel_id,
+                    })
+                    .collect();
+                channels.sort_by(|a, b| {
+                    b.num_recent_messages
+                        .cmp(&a.num_recent_messages)
+                        .then(b.last_message_time.cmp(&a.last_message_time))
+                });
+                channels.truncate(limit as usize);
+                Some(serde_json::to_string(&TrendingChannelsResponse { channels }).unwrap())
+            },
+            GetRequest::TopTipped { channel_id, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = channel_id.map(|channel_id| self.get_accessible_channel(channel_id, reader_id.as_ref()));
+                let top_tipped = channel.as_ref().map(|channel| &channel.top_tipped).unwrap_or(&self.top_tipped);
+                let accounts = top_tipped
+                    .top(limit)
+                    .map(|(account_id, amount)| TippedAccount {
+                        account_id: account_id.clone(),
+                        amount: amount.to_string(),
+                    })
+                    .collect();
+                Some(serde_json::to_string(&TopTippedResponse { accounts }).unwrap())
+            },
+            GetRequest::TopPosters { channel_id, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = channel_id.map(|channel_id| self.get_accessible_channel(channel_id, reader_id.as_ref()));
+                let top_posters = channel.as_ref().map(|channel| &channel.top_posters).unwrap_or(&self.top_posters);
+                let accounts = top_posters
+                    .top(limit)
+                    .map(|(account_id, num_messages)| Poster {
+                        account_id: account_id.clone(),
+                        num_messages: *num_messages as u64,
+                    })
+                    .collect();
+                Some(serde_json::to_string(&TopPostersResponse { accounts }).unwrap())
+            },
+            GetRequest::ChannelStatus { channel_id, reader_id } => {
+                Some(serde_json::to_string(&self.channel_status(channel_id, reader_id.as_ref())).unwrap())
+            },
+            GetRequest::ChannelMessages {
+                channel_id,
+                from_index,
+                limit,
+                reader_id,
+                include_profiles,
+                viewer_id,
+            } => {
+                let response = self.channel_messages(
+                    channel_id,
+                    from_index,
+                    limit,
+                    reader_id.as_ref(),
+                    include_profiles,
+                    viewer_id.as_ref(),
+                );
+                Some(serde_json::to_string(&response).unwrap())
+            },
+            GetRequest::ChannelMessagesReverse { channel_id, from_end, limit, reader_id, viewer_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let num_messages = channel.messages.len();
+                let messages = (0..num_messages.saturating_sub(from_end))
+                    .rev()
+                    .take(limit as usize)
+                    .map(|index| IndexedMessage {
+                        index,
+                        message: self.message_view(&channel, index, viewer_id.as_ref()),
+                    })
+                    .collect();
+                Some(serde_json::to_string(&ChannelMessagesReverseResponse {
+                    num_messages,
+                    messages,
+                }).unwrap())
+            },
+            GetRequest::ChannelMessagesByTime { channel_id, from_time, to_time, limit, reader_id, viewer_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let mut messages = Vec::new();
+                let mut index = channel.first_message_since(from_time);
+                while (messages.len() as u64) < limit && index < channel.messages.len() {
+                    if channel.message(index).unwrap().time >= to_time {
+                        break;
+                    }
+                    messages.push(IndexedMessage {
+                        index,
+                        message: self.message_view(&channel, index, viewer_id.as_ref()),
+                    });
+                    index += 1;
+                }
+                Some(serde_json::to_string(&ChannelMessagesByTimeResponse { messages }).unwrap())
+            },
+            GetRequest::MessagesByIndices { channel_id, indices, reader_id, viewer_id } => {
+                assert!(indices.len() as u64 <= self.config.max_page_size, "Too many message indices");
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let messages = indices
+                    .into_iter()
+                    .filter(|index| *index < channel.messages.len())
+                    .map(|index| IndexedMessage {
+                        index,
+                        message: self.message_view(&channel, index, viewer_id.as_ref()),
+                    })
+                    .collect();
+                Some(serde_json::to_string(&MessagesByIndicesResponse {
+                    messages,
+                }).unwrap())
+            },
+            GetRequest::ThreadMessages { channel_id, root_index, from_index, limit, reader_id, viewer_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let replies = channel.threads.get(&root_index);
+                let num_replies = replies.as_ref().map(|replies| replies.len()).unwrap_or(0);
+                let mut messages = Vec::new();
+                if let Some(replies) = replies {
+                    let mut index = from_index;
+                    while (messages.len() as u64) < limit && index < replies.len() {
+                        let message_index = replies.get(index).unwrap();
+                        messages.push(IndexedMessage {
+                            index: message_index,
+                            message: self.message_view(&channel, message_index, viewer_id.as_ref()),
+                        });
+                        index += 1;
+                    }
+                }
+                Some(serde_json::to_string(&ThreadMessagesResponse {
+                    num_replies,
+                    messages,
+                }).unwrap())
+            },
+            GetRequest::MessageHistory { channel_id, message_index, reader_id } => {
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let message = channel.message(message_index).expect("Message doesn't exist");
+                let revisions = if self.is_redacted(&message, reader_id.as_ref()) {
+                    vec![]
+                } else {
+                    channel
+                        .revisions
+                        .get(&message_index)
+                        .map(|revisions| revisions.to_vec())
+                        .unwrap_or_default()
+                };
+                Some(serde_json::to_string(&MessageHistoryResponse {
+                    revisions,
+                }).unwrap())
+            },
+            GetRequest::Mentions { account_id, from_index, limit } => {
+                let limit = page_limit(limit, &self.config);
+                let mentions = self.mentions.get(&account_id);
+                let num_mentions = mentions.as_ref().map(|mentions| mentions.len()).unwrap_or(0);
+                let mut messages = Vec::new();
+                if let Some(mentions) = mentions {
+                    let mut index = from_index;
+                    while (messages.len() as u64) < limit && index < mentions.len() {
+                        let mention = mentions.get(index).unwrap();
+                        let channel = self.read_channel(&mention.channel_id).unwrap();
+                        if channel.can_access(&account_id) {
+                            messages.push(MentionedMessage {
+                                index: mention.message_index,
+                                message: self.message_view(&channel, mention.message_index, Some(&account_id)),
+                                channel_id: channel.channel_id,
+                            });
+                        }
+                        index += 1;
+                    }
+                }
+                Some(serde_json::to_string(&MentionsResponse {
+                    num_mentions,
+                    messages,
+                }).unwrap())
+            },
+            GetRequest::ModerationLog { channel_id, from_index, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let mut entries = Vec::new();
+                let mut index = from_index;
+                while (entries.len() as u64) < limit && index < channel.moderation_log.len() {
+                    entries.push(channel.moderation_log.get(index).unwrap());
+                    index += 1;
+                }
+                Some(serde_json::to_string(&ModerationLogResponse {
+                    num_entries: channel.moderation_log.len(),
+                    entries,
+                }).unwrap())
+            },
+            GetRequest::HistoryCommitments { channel_id, from_index, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let mut commitments = Vec::new();
+                let mut index = from_index;
+                while (commitments.len() as u64) < limit && index < channel.history_commitments.len() {
+                    commitments.push(channel.history_commitments.get(index).unwrap());
+                    index += 1;
+                }
+                Some(serde_json::to_string(&HistoryCommitmentsResponse {
+                    num_commitments: channel.history_commitments.len(),
+                    commitments,
+                }).unwrap())
+            },
+            GetRequest::ModerationQueue { channel_id, from_index, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let message_indices = channel.reports.keys_as_vector();
+                let mut messages = Vec::new();
+                let mut index = from_index;
+                while (messages.len() as u64) < limit && index < message_indices.len() {
+                    let message_index = message_indices.get(index).unwrap();
+                    messages.push(ReportedMessage {
+                        message_index,
+                        hidden: channel.message(message_index).unwrap().hidden,
+                        reports: channel.reports.get(&message_index).unwrap(),
+                    });
+                    index += 1;
+                }
+                Some(serde_json::to_string(&ModerationQueueResponse {
+                    num_reported_messages: message_indices.len(),
+                    messages,
+                }).unwrap())
+            },
+            GetRequest::PendingInvites { account_id } => {
+                let invites = self
+                    .invites
+                    .get(&account_id)
+                    .map(|invites| invites.values().collect())
+                    .unwrap_or_default();
+                Some(serde_json::to_string(&PendingInvitesResponse {
+                    invites,
+                }).unwrap())
+            },
+            GetRequest::ChannelMembers { channel_id, from_index, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let account_ids = channel.members.keys_as_vector();
+                let joined_at = channel.members.values_as_vector();
+                let mut members = Vec::new();
+                let mut index = from_index;
+                while (members.len() as u64) < limit && index < account_ids.len() {
+                    members.push(ChannelMember {
+                        account_id: account_ids.get(index).unwrap(),
+                        joined_at: joined_at.get(index).unwrap(),
+                    });
+                    index += 1;
+                }
+                Some(serde_json::to_string(&ChannelMembersResponse {
+                    num_members: channel.members.len(),
+                    members,
+                }).unwrap())
+            },
+            GetRequest::ExportChannel { channel_id, from_index, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let num_messages = channel.messages.len();
+                let mut messages = Vec::new();
+                let mut index = from_index;
+                while (messages.len() as u64) < limit && index < num_messages {
+                    messages.push(ExportedMessage {
+                        index,
+                        message: self.message_view(&channel, index, reader_id.as_ref()),
+                    });
+                    index += 1;
+                }
+                Some(serde_json::to_string(&ExportChannelResponse {
+                    num_messages,
+                    messages,
+                    next_index: if index < num_messages { Some(index) } else { None },
+                }).unwrap())
+            },
+            GetRequest::Bans { channel_id, from_index, limit, reader_id } => {
+                let limit = page_limit(limit, &self.config);
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let account_ids = channel.bans.keys_as_vector();
+                let all_bans = channel.bans.values_as_vector();
+                let mut bans = Vec::new();
+                let mut index = from_index;
+                while (bans.len() as u64) < limit && index < account_ids.len() {
+                    bans.push(BannedAccount {
+                        account_id: account_ids.get(index).unwrap(),
+                        ban: all_bans.get(index).unwrap(),
+                    });
+                    index += 1;
+                }
+                Some(serde_json::to_string(&BansResponse {
+                    num_bans: channel.bans.len(),
+                    bans,
+                }).unwrap())
+            },
+            GetRequest::Profiles { account_ids } => {
+                let profiles = account_ids
+                    .into_iter()
+                    .filter_map(|account_id| self.profiles.get(&account_id).map(|profile| (account_id, profile)))
+                    .collect();
+                Some(serde_json::to_string(&ProfilesResponse { profiles }).unwrap())
+            },
+            GetRequest::Presence { account_ids } => {
+                let last_active = account_ids
+                    .into_iter()
+                    .filter_map(|account_id| self.last_active.get(&account_id).map(|time| (account_id, time)))
+                    .collect();
+                Some(serde_json::to_string(&PresenceResponse { last_active }).unwrap())
+            },
+            GetRequest::EncryptionKeys { account_ids } => {
+                let encryption_keys = account_ids
+                    .into_iter()
+                    .filter_map(|account_id| self.encryption_keys.get(&account_id).map(|key| (account_id, key)))
+                    .collect();
+                Some(serde_json::to_string(&EncryptionKeysResponse { encryption_keys }).unwrap())
+            },
+            GetRequest::SigningNonce { account_id } => {
+                let nonce = self.signing_nonces.get(&account_id).unwrap_or(0);
+                Some(serde_json::to_string(&SigningNonceResponse { nonce }).unwrap())
+            },
+            GetRequest::CustomEmoji { channel_id } => {
+                let custom_emoji = self.custom_emoji.get(&channel_id).unwrap_or_default();
+                Some(serde_json::to_string(&CustomEmojiResponse { custom_emoji }).unwrap())
+            },
+            GetRequest::ReactionsBy { account_id, channel_id } => {
+                let key = account_channel_key(&account_id, &env::sha256(channel_id.as_bytes()));
+                let reactions = self.account_reactions.get(&key).unwrap_or_default();
+                Some(serde_json::to_string(&ReactionsByResponse { reactions }).unwrap())
+            },
+            GetRequest::TypingAccounts { channel_id, reader_id } => {
+                self.get_accessible_channel(channel_id.clone(), reader_id.as_ref());
+                let time = env::block_timestamp() / 1000000;
+                let account_ids = self
+                    .typing
+                    .get(&channel_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|indicator| indicator.expires_at > time)
+                    .map(|indicator| indicator.account_id)
+                    .collect();
+                Some(serde_json::to_string(&TypingAccountsResponse { account_ids }).unwrap())
+            },
+            GetRequest::ChannelActivity { channel_id, from_day, to_day, reader_id } => {
+                let channel = self.get_accessible_channel(channel_id, reader_id.as_ref());
+                let to_day = to_day.min(from_day.saturating_add(self.config.max_page_size.saturating_sub(1)));
+                let days = (from_day..=to_day)
+                    .filter_map(|day| {
+                        channel.daily_activity.get(&day).map(|num_messages| DailyActivity { day, num_messages })
+                    })
+                    .collect();
+                Some(serde_json::to_string(&ChannelActivityResponse { days }).unwrap())
+            },
+            GetRequest::Analytics {} => {
+                Some(serde_json::to_string(&AnalyticsResponse {
+                    top_channels: self.analytics.top_channels.clone(),
+                    num_senders: self.analytics.num_senders,
+                    num_recent_messages: self.analytics.num_recent_messages(env::block_timestamp() / 1000000),
+                    storage_usage: env::storage_usage(),
+                }).unwrap())
+            },
+            GetRequest::Reputation { account_id } => {
+                let reputation = self.reputations.get(&account_id).unwrap_or_default();
+                let score = reputation.score();
+                Some(serde_json::to_string(&ReputationResponse { reputation, score }).unwrap())
+            },
+            GetRequest::AccountChannels { account_id, from_index, limit } => {
+                let limit = page_limit(limit, &self.config);
+                let account_channels = self.account_channels.get(&account_id);
+                let num_channels = account_channels.as_ref().map(|channels| channels.len()).unwrap_or(0);
+                let mut channels = Vec::new();
+                if let Some(account_channels) = account_channels {
+                    let channel_ids = account_channels.as_vector();
+                    let mut index = from_index;
+                    while (channels.len() as u64) < limit && index < channel_ids.len() {
+                        let channel = self.get_channel(channel_ids.get(index).unwrap());
+                        channels.push(channel.into_info());
+                        index += 1;
+                    }
+                }
+                Some(serde_json::to_string(&ChannelsResponse {
+                    num_channels,
+                    channels,
+                }).unwrap())
+            },
+            GetRequest::AppKeys { app_id, from_index, limit } => {
+                let limit = page_limit(limit, &self.config);
+                let app_keys = self.app_keys.get(&app_id);
+                let num_keys = app_keys.as_ref().map(|keys| keys.len()).unwrap_or(0);
+                let mut keys = Vec::new();
+                if let Some(app_keys) = app_keys {
+                    let stored_keys = app_keys.as_vector();
+                    let mut index = from_index;
+                    while (keys.len() as u64) < limit && index < stored_keys.len() {
+                        keys.push(stored_keys.get(index).unwrap());
+                        index += 1;
+                    }
+                }
+                Some(serde_json::to_string(&AppKeysResponse {
+                    num_keys,
+                    keys,
+                }).unwrap())
+            },
+            GetRequest::UnreadCounts { account_id, channel_ids } => {
+                let unread_counts = channel_ids
+                    .into_iter()
+                    .map(|channel_id| {
+                        let channel = self.get_channel(channel_id);
+                        let channel_hash = env::sha256(channel.channel_id.as_bytes());
+                        let last_read_index = self.read_markers.get(&read_marker_key(&account_id, &channel_hash));
+                        let num_read = last_read_index.map(|index| index + 1).unwrap_or(0);
+                        UnreadCount {
+                            num_unread: channel.messages.len().saturating_sub(num_read),
+                            channel_id: channel.channel_id,
+                            last_read_index,
+                        }
+                    })
+                    .collect();
+                Some(serde_json::to_string(&UnreadCountsResponse {
+                    unread_counts,
+                }).unwrap())
+            },
+        }
+    }
+
+    pub fn assert_not_paused(&self) {
+        assert!(!self.paused, "The contract is paused");
+    }
+
+    pub fn assert_migrated(&self) {
+        assert!(self.legacy_channels.len() == 0, "Channels are being migrated");
+    }
+
+    pub fn save_channel(&mut self, channel: &Channel) {
+        if !self.write_channel(channel) {
+            emit_event(ChatEvent::ChannelCreated(vec![ChannelEventData {
+                channel_id: &channel.channel_id,
+            }]));
+        }
+    }
+
+    /// Reads the channel stored under its `channel_key`.
+    fn read_channel(&self, channel_id: &ChannelId) -> Option<Channel> {
+        env::storage_read(&channel_key(channel_id)).map(|bytes| {
+            Channel::from(VersionedChannel::try_from_slice(&bytes).expect("Can't deserialize the channel"))
+        })
+    }
+
+    /// Stores the channel in the current layout. Returns whether the channel already existed.
+    fn write_channel(&mut self, channel: &Channel) -> bool {
+        let existed = env::storage_write(&channel_key(&channel.channel_id), &VersionedChannel::current_bytes(channel));
+        if !existed {
+            self.channel_ids.push(&channel.channel_id);
+        }
+        existed
+    }
+
+    /// Adds a new message to the channel, saves the channel and records mentions of the given
+    /// accounts. Returns the index of the message.
+    pub fn post_to_channel(&mut self, channel: &mut Channel, mut message: Message, mentions: Vec<AccountId>) -> u64 {
+        let sender_id = message.sender_id.clone();
+        let time = message.time;
+        self.record_post(&sender_id, time);
+        if channel.min_reputation > 0 && !channel.is_moderator(&sender_id) {
+            let score = self.reputations.get(&sender_id).unwrap_or_default().score();
+            assert!(score >= channel.min_reputation, "Not enough reputation to post to the channel");
+        }
+        let mut new_sender = false;
+        self.update_reputation(&sender_id, |reputation| {
+            new_sender = reputation.num_messages == 0;
+            reputation.num_messages += 1;
+        });
+        if message.storage_payer_id.is_none() {
+            message.storage_payer_id = Some(sender_id.clone());
+        }
+        let message_index = channel.add_message(message);
+        self.analytics.record_message(new_sender, time);
+        if channel.kind == ChannelKind::Public {
+            self.analytics.record_top_channel(&channel.channel_id, channel.messages.len());
+            self.analytics.record_active_channel(&channel.channel_id);
+        }
+        self.hold_message_deposit(channel, message_index, &sender_id, time);
+        if !self.posted_channel_ids.contains(&channel.channel_id) {
+            self.posted_channel_ids.push(channel.channel_id.clone());
+        }
+        self.save_channel(channel);
+        self.record_mentions(channel, message_index, &sender_id, mentions);
+        self.total_num_messages += 1;
+        emit_event(ChatEvent::MessagePosted(vec![MessageEventData {
+            channel_id: &channel.channel_id,
+            message_index,
+            sender_id: &sender_id,
+        }]));
+        // Notifications are best-effort: a failing receiver doesn't affect the posted message.
+        for receiver_id in &channel.receivers {
+            notify(
+                receiver_id.clone(),
+                b"on_chat_message",
+                serde_json::json!({
+                    "channel_id": channel.channel_id,
+                    "index": message_index,
+                    "sender_id": sender_id,
+                }),
+                GAS_FOR_MESSAGE_NOTIFICATION,
+            );
+        }
+        message_index
+    }
+
+    /// Moves the configured message deposit from the storage balance of the sender to the deposits
+    /// held for the message.
+    fn hold_message_deposit(&mut self, channel: &Channel, message_index: u64, sender_id: &AccountId, time: u64) {
+        let amount: Balance = self.config.message_deposit.parse().unwrap();
+        if amount == 0 || sender_id == &env::current_account_id() {
+            return;
+        }
+        let balance = self.storage_balances.get(sender_id).unwrap_or(0);
+        assert!(balance >= amount, "Not enough storage balance to cover the message deposit");
+        self.storage_balances.insert(sender_id, &(balance - amount));
+        self.message_deposits.insert(
+            &message_key(&env::sha256(channel.channel_id.as_bytes()), message_index),
+            &HeldDeposit {
+                account_id: sender_id.clone(),
+                amount,
+                release_time: time + self.config.message_deposit_cooldown_ms,
+            },
+        );
+    }
+
+    /// Moves the deposit held for a message removed by a moderator to the treasury.
+    fn forfeit_message_deposit(&mut self, channel: &Channel, message_index: u64) {
+        let key = message_key(&env::sha256(channel.channel_id.as_bytes()), message_index);
+        if let Some(deposit) = self.message_deposits.remove(&key) {
+            self.collect_fee(deposit.amount);
+        }
+    }
+
+    fn update_reputation(&mut self, account_id: &AccountId, update: impl FnOnce(&mut Reputation)) {
+        let mut reputation = self.reputations.get(account_id).unwrap_or_default();
+        let num_messages = reputation.num_messages;
+        update(&mut reputation);
+        self.reputations.insert(account_id, &reputation);
+        if reputation.num_messages != num_messages {
+            self.top_posters.record(account_id, u128::from(reputation.num_messages));
+        }
+    }
+
+    /// Moves the tip from the storage balance of the sender to the author of the message and the
+    /// treasury.
+    fn tip_message(&mut self, channel: &mut Channel, message_index: u64, sender_id: &AccountId, amount: Balance) {
+        assert!(amount > 0, "Tip amount should be positive");
+        let author_id = channel.tipped_message_author(message_index, sender_id);
+        self.pay_from_storage_balance(sender_id, &author_id, amount, self.config.tip_fee_bps);
+        let key = message_key(&env::sha256(channel.channel_id.as_bytes()), message_index);
+        self.tips.insert(&key, &(self.tips.get(&key).unwrap_or(0) + amount));
+        let channel_total = channel.tips_received.get(&author_id).unwrap_or(0) + amount;
+        channel.tips_received.insert(&author_id, &channel_total);
+        channel.top_tipped.record(&author_id, channel_total);
+        let total = self.tips_received.get(&author_id).unwrap_or(0) + amount;
+        self.tips_received.insert(&author_id, &total);
+        self.top_tipped.record(&author_id, total);
+    }
+
+    /// Moves the amount from the storage balance of the payer to the recipient. The given share in
+    /// basis points goes to the treasury.
+    fn pay_from_storage_balance(
+        &mut self,
+        payer_id: &AccountId,
+        recipient_id: &AccountId,
+        amount: Balance,
+        fee_bps: u64,
+    ) {
+        let balance = self.storage_balances.get(payer_id).unwrap_or(0);
+        assert!(balance >= amount, "Not enough storage balance to cover the payment");
+        self.storage_balances.insert(payer_id, &(balance - amount));
+        let fee = amount * Balance::from(fee_bps) / 10_000;
+        self.collect_fee(fee);
+        if amount > fee {
+            Promise::new(recipient_id.clone()).transfer(amount - fee);
+        }
+    }
+
+    /// Returns the cost of the freed storage to the storage balance of the account that paid for it.
+    fn refund_storage(&mut self, freed_storage: FreedStorage) {
+        if freed_storage.num_bytes == 0 || freed_storage.account_id == env::current_account_id() {
+            return;
+        }
+        if let Some(balance) = self.storage_balances.get(&freed_storage.account_id) {
+            let refund = Balance::from(freed_storage.num_bytes) * STORAGE_PRICE_PER_BYTE;
+            self.storage_balances.insert(&freed_storage.account_id, &(balance + refund));
+        }
+    }
+
+    fn collect_fee(&mut self, amount: Balance) {
+        self.treasury.balance += amount;
+        self.treasury.total_collected += amount;
+    }
+
+    fn add_token_balance(&mut self, account_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        let mut balances =
+            self.token_balances.get(account_id).unwrap_or_else(|| Map::new(token_balances_key(account_id)));
+        balances.insert(token_id, &(balances.get(token_id).unwrap_or(0) + amount));
+        self.token_balances.insert(account_id, &balances);
+    }
+
+    /// Counts the post towards the rate limit of the sender.
+    fn record_post(&mut self, sender_id: &AccountId, time: u64) {
+        let mut window = match self.post_windows.get(sender_id) {
+            Some(window) if time < window.start + self.config.post_window_ms => window,
+            _ => PostWindow { start: time, num_posts: 0 },
+        };
+        assert!(window.num_posts < self.config.max_posts_per_window, "Too many messages, wait before posting again");
+        window.num_posts += 1;
+        self.post_windows.insert(sender_id, &window);
+    }
+
+    pub fn add_member(&mut self, channel: &mut Channel, account_id: &AccountId, time: u64) {
+        if channel.is_member(account_id) {
+            return;
+        }
+        channel.members.insert(account_id, &time);
+        let mut account_channels = self
+            .account_channels
+            .get(account_id)
+            .unwrap_or_else(|| Set::new(account_channels_key(account_id)));
+        account_channels.insert(&channel.channel_id);
+        self.account_channels.insert(account_id, &account_channels);
+    }
+
+    pub fn remove_member(&mut self, channel: &mut Channel, account_id: &AccountId) {
+        assert!(channel.members.remove(account_id).is_some(), "Not a member");
+        let mut account_channels = self.account_channels.get(account_id).unwrap();
+        account_channels.remove(&channel.channel_id);
+        if account_channels.len() > 0 {
+            self.account_channels.insert(account_id, &account_channels);
+        } else {
+            self.account_channels.remove(account_id);
+        }
+    }
+
+    /// Replaces the tags of the channel and updates the tag index.
+    pub fn set_tags(&mut self, channel: &mut Channel, tags: Vec<Tag>) {
+        for tag in &channel.tags {
+            let mut tag_channels = self.tag_channels.get(tag).unwrap();
+            tag_channels.remove(&channel.channel_id);
+            if tag_channels.len() > 0 {
+                self.tag_channels.insert(tag, &tag_channels);
+            } else {
+                self.tag_channels.remove(tag);
+            }
+        }
+        for tag in &tags {
+            let mut tag_channels = self.tag_channels.get(tag).unwrap_or_else(|| Set::new(tag_channels_key(tag)));
+            tag_channels.insert(&channel.channel_id);
+            self.tag_channels.insert(tag, &tag_channels);
+        }
+        channel.tags = tags;
+    }
+
+    /// Records the mentions and notifies the mention receivers of the mentioned accounts. The
+    /// notifications are best-effort like the notifications of channel receivers.
+    pub fn record_mentions(
+        &mut self,
+        channel: &Channel,
+        message_index: u64,
+        sender_id: &AccountId,
+        mentions: Vec<AccountId>,
+    ) {
+        for account_id in mentions {
+            let mut account_mentions = self
+                .mentions
+                .get(&account_id)
+                .unwrap_or_else(|| Vector::new(mentions_key(&account_id)));
+            account_mentions.push(&Mention { channel_id: channel.channel_id.clone(), message_index });
+            self.mentions.insert(&account_id, &account_mentions);
+            if let Some(receiver_id) = self.mention_receivers.get(&account_id) {
+                notify(
+                    receiver_id,
+                    b"on_mention",
+                    serde_json::json!({
+                        "channel_id": channel.channel_id,
+                        "index": message_index,
+                        "sender_id": sender_id,
+                    }),
+                    GAS_FOR_MENTION_NOTIFICATION,
+                );
+            }
+        }
+    }
+
+    /// Returns profiles of the senders of the given messages.
+    pub fn sender_profiles(&self, messages: &[MessageView]) -> BTreeMap<AccountId, Profile> {
+        let mut profiles = BTreeMap::new();
+        for view in messages {
+            if !profiles.contains_key(&view.message.sender_id) {
+                if let Some(profile) = self.profiles.get(&view.message.sender_id) {
+                    profiles.insert(view.message.sender_id.clone(), profile);
+                }
+            }
+        }
+        profiles
+    }
+
+    /// Returns the message with its reactions. The message is redacted if the viewer blocked the
+    /// sender.
+    /// Whether the message is hidden from the given viewer, either by moderation or by the viewer's own blocks.
+    fn is_redacted(&self, message: &Message, viewer_id: Option<&AccountId>) -> bool {
+        message.hidden
+            || self.global_bans.get(&message.sender_id).map(|ban| ban.hide_messages).unwrap_or(false)
+            || viewer_id.map(|viewer_id| self.is_blocked(viewer_id, &message.sender_id)).unwrap_or(false)
+    }
+
+    pub fn message_view(&self, channel: &Channel, message_index: u64, viewer_id: Option<&AccountId>) -> MessageView {
+        let mut message = channel.message(message_index).expect("Message doesn't exist");
+        let redacted = self.is_redacted(&message, viewer_id);
+        if redacted {
+            message.redact();
+        }
+        let key = message_key(&env::sha256(channel.channel_id.as_bytes()), message_index);
+        let reactions = self
+            .reactions
+            .get(&key)
+            .map(|emojis| {
+                emojis
+                    .iter()
+                    .map(|(emoji, reactors)| ReactionCount { emoji, count: reactors.len() })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let tips = self.tips.get(&key).map(|tips| tips.to_string());
+        let (poll_votes, poll_weights) = match &message.body {
+            MessageBody::Poll { options, weight_token_id, .. } => {
+                let tally = self.poll_votes.get(&key).unwrap_or_else(|| PollTally::new(options.len()));
+                let weights = weight_token_id
+                    .as_ref()
+                    .map(|_| tally.weights.iter().map(|weight| weight.to_string()).collect());
+                (Some(tally.votes), weights)
+            },
+            _ => (None, None),
+        };
+        let chain_link = hex_encode(&channel.chain_links.get(message_index).unwrap());
+        let replies = channel.threads.get(&message_index).filter(|replies| !replies.is_empty());
+        let reply_count = replies.as_ref().map(|replies| replies.len());
+        let last_reply_time = replies
+            .map(|replies| channel.message(replies.get(replies.len() - 1).unwrap()).unwrap().time);
+        MessageView {
+            message,
+            reactions,
+            tips,
+            poll_votes,
+            poll_weights,
+            chain_link,
+            reply_count,
+            last_reply_time,
+            redacted,
+        }
+    }
+
+    pub fn assert_no_banned_words(&self, body: &MessageBody) {
+        if let Some(text) = body.text() {
+            let text = text.to_lowercase();
+            assert!(
+                !self.banned_words.iter().any(|word| text.contains(&word)),
+                "The message contains a banned word"
+            );
+        }
+    }
+
+    /// Returns whether the account blocked the other account.
+    pub fn is_blocked(&self, account_id: &AccountId, blocked_id: &AccountId) -> bool {
+        self.blocked_accounts.get(account_id).map(|blocked| blocked.contains(blocked_id)).unwrap_or(false)
+    }
+
+    /// Records the vote of the account. Votes in weighted polls need the weight of the account.
+    pub fn vote(
+        &mut self,
+        channel: &Channel,
+        message_index: u64,
+        sender_id: &AccountId,
+        option: u64,
+        weight: Option<Balance>,
+        time: u64,
+    ) {
+        let (num_options, weight_token_id) = channel.poll_to_vote(message_index, option, time);
+        assert!(weight_token_id.is_none() || weight.is_some(), "Weighted polls are voted with `vote_weighted`");
+        let key = message_key(&env::sha256(channel.channel_id.as_bytes()), message_index);
+        let mut voters = self.poll_voters.get(&key).unwrap_or_else(|| Map::new(poll_voters_key(&key)));
+        assert!(voters.insert(sender_id, &option).is_none(), "Already voted in this poll");
+        self.poll_voters.insert(&key, &voters);
+        let mut tally = self.poll_votes.get(&key).unwrap_or_else(|| PollTally::new(num_options));
+        tally.votes[option as usize] += 1;
+        if let Some(weight) = weight {
+            tally.weights[option as usize] += weight;
+        }
+        self.poll_votes.insert(&key, &tally);
+    }
+
+    pub fn add_reaction(&mut self, channel: &Channel, message_index: u64, sender_id: &AccountId, emoji: Emoji) {
+        verify_emoji(&emoji);
+        let message = channel.message(message_index).expect("Message doesn't exist");
+        assert!(!message.deleted, "The message is deleted");
+        let key = message_key(&env::sha256(channel.channel_id.as_bytes()), message_index);
+        let mut emojis = self.reactions.get(&key).unwrap_or_else(|| Map::new(emojis_key(&key)));
+        let mut reactors = emojis.get(&emoji).unwrap_or_else(|| Set::new(reactors_key(&key, &emoji)));
+        assert!(!reactors.contains(sender_id), "Already reacted with this emoji");
+        reactors.insert(sender_id);
+        emojis.insert(&emoji, &reactors);
+        self.reactions.insert(&key, &emojis);
+        let account_key = account_channel_key(sender_id, &env::sha256(channel.channel_id.as_bytes()));
+        let mut account_reactions = self.account_reactions.get(&account_key).unwrap_or_default();
+        account_reactions.entry(message_index).or_default().push(emoji);
+        self.account_reactions.insert(&account_key, &account_reactions);
+        if &message.sender_id != sender_id {
+            self.update_reputation(&message.sender_id, |reputation| reputation.num_reactions_received += 1);
+        }
+    }
+
+    pub fn remove_reaction(&mut self, channel: &Channel, message_index: u64, sender_id: &AccountId, emoji: Emoji) {
+        let key = message_key(&env::sha256(channel.channel_id.as_bytes()), message_index);
+        let mut emojis = self.reactions.get(&key).expect("The message has no reactions");
+        let mut reactors = emojis.get(&emoji).expect("The message has no reactions with this emoji");
+        assert!(reactors.remove(sender_id), "Not reacted with this emoji");
+        if reactors.len() > 0 {
+            emojis.insert(&emoji, &reactors);
+        } else {
+            emojis.remove(&emoji);
+        }
+        if emojis.len() > 0 {
+            self.reactions.insert(&key, &emojis);
+        } else {
+            self.reactions.remove(&key);
+        }
+        let account_key = account_channel_key(sender_id, &env::sha256(channel.channel_id.as_bytes()));
+        let mut account_reactions = self.account_reactions.get(&account_key).unwrap_or_default();
+        if let Some(emojis) = account_reactions.get_mut(&message_index) {
+            emojis.retain(|reacted_emoji| reacted_emoji != &emoji);
+            if emojis.is_empty() {
+                account_reactions.remove(&message_index);
+            }
+        }
+        if account_reactions.is_empty() {
+            self.account_reactions.remove(&account_key);
+        } else {
+            self.account_reactions.insert(&account_key, &account_reactions);
+        }
+        let message = channel.message(message_index).unwrap();
+        if &message.sender_id != sender_id {
+            self.update_reputation(&message.sender_id, |reputation| {
+                reputation.num_reactions_received = reputation.num_reactions_received.saturating_sub(1)
+            });
+        }
+    }
+}
+
+
+impl Reputation {
+    /// One point for every message and received reaction, minus a penalty for every report.
+    pub fn score(&self) -> u64 {
+        (self.num_messages + self.num_reactions_received)
+            .saturating_sub(self.num_reports * REPUTATION_REPORT_PENALTY)
+    }
+}
+
+impl MessageBody {
+    /// Returns accounts mentioned in the text of the message, excluding the sender.
+    pub fn mentions(&self, sender_id: &AccountId, config: &Config) -> Vec<AccountId> {
+        match self {
+            MessageBody::Text { text } => extract_mentions(text)
+                .into_iter()
+                .filter(|account_id| account_id != sender_id)
+                .take(config.max_num_mentions as usize)
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the text shown to readers, if the message has any.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            MessageBody::Text { text } | MessageBody::System { text } => Some(text),
+            MessageBody::Link { title, .. } => Some(title),
+            MessageBody::Poll { question, .. } => Some(question),
+            MessageBody::Image { .. } | MessageBody::Encrypted { .. } => None,
+        }
+    }
+}
+
+impl Message {
+    pub fn new(sender_id: AccountId, body: MessageBody, attachments: Vec<Attachment>, time: u64) -> Self {
+        Self {
+            time,
+            sender_id,
+            body,
+            attachments,
+            reply_to: None,
+            forwarded_from: None,
+            edited_at: None,
+            deleted: false,
+            hidden: false,
+            crosspost_id: None,
+            bridged_from: None,
+            storage_payer_id: None,
+        }
+    }
+
+    /// Replaces the content of the message with a tombstone and marks it deleted.
+    pub fn tombstone(&mut self) {
+        self.redact();
+        self.deleted = true;
+    }
+
+    /// Replaces the content of the message with a tombstone.
+    pub fn redact(&mut self) {
+        self.body = MessageBody::Text { text: DELETED_MESSAGE_TEXT.to_string() };
+        self.attachments.clear();
+    }
+}
+
+impl From<VersionedMessage> for Message {
+    fn from(message: VersionedMessage) -> Self {
+        match message {
+            VersionedMessage::V1(message) => message,
+        }
+    }
+}
+
+impl From<VersionedChannel> for Channel {
+    fn from(channel: VersionedChannel) -> Self {
+        match channel {
+            VersionedChannel::V1(channel) => channel,
+        }
+    }
+}
+
+impl VersionedChannel {
+    /// Serializes the channel as the current variant without taking ownership of it.
+    pub fn current_bytes(channel: &Channel) -> Vec<u8> {
+        // The variant index of `VersionedChannel::V1`.
+        let mut bytes = vec![0];
+        channel.serialize(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+impl VersionedState {
+    /// Reads the contract state and detects its storage layout.
+    pub fn read() -> Self {
+        let bytes = env::storage_read(b"STATE").unwrap_or_else(|| env::panic(b"The contract is not initialized"));
+        if let Ok(contract) = MetanearChat::try_from_slice(&bytes) {
+            VersionedState::Current(Box::new(contract))
+        } else if let Ok(contract) = MetanearChatV1::try_from_slice(&bytes) {
+            VersionedState::V1(contract)
+        } else {
+            env::panic(b"Unknown state layout")
+        }
+    }
+}
+
+impl ChannelGate {
+    /// Returns whether the result of the gate check call passes the gate.
+    pub fn is_passed(&self, result: &[u8]) -> bool {
+        match self {
+            ChannelGate::FungibleToken { min_balance, .. } => {
+                let balance = serde_json::from_slice::<String>(result)
+                    .ok()
+                    .and_then(|balance| balance.parse::<u128>().ok());
+                balance.map(|balance| balance >= min_balance.parse().unwrap()).unwrap_or(false)
+            },
+            ChannelGate::NonFungibleToken { .. } => serde_json::from_slice::<Vec<serde_json::Value>>(result)
+                .map(|tokens| !tokens.is_empty())
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl Channel {
+    /// Checks that the message is an open poll with the option. Returns the number of options and
+    /// the token that weights the votes.
+    pub fn poll_to_vote(&self, message_index: u64, option: u64, time: u64) -> (usize, Option<AccountId>) {
+        let message = self.message(message_index).expect("Message doesn't exist");
+        assert!(!message.deleted, "The message is deleted");
+        match message.body {
+            MessageBody::Poll { options, closes_at, weight_token_id, .. } => {
+                assert!(time < closes_at, "The poll is closed");
+                assert!(option < options.len() as u64, "Poll option doesn't exist");
+                (options.len(), weight_token_id)
+            },
+            _ => env::panic(b"The message is not a poll"),
+        }
+    }
+
+    pub fn new(channel_id: ChannelId, owner_id: AccountId) -> Self {
+        let channel_hash = env::sha256(channel_id.as_bytes());
+        Self {
+            owner_id,
+            messages: Vector::new(messages_key_from_hash(channel_hash.clone())),
+            threads: Map::new(threads_key_from_hash(channel_hash.clone())),
+            revisions: Map::new(revisions_key_from_hash(channel_hash.clone())),
+            moderators: Set::new(moderators_key_from_hash(channel_hash.clone())),
+            moderation_log: Vector::new(moderation_log_key_from_hash(channel_hash.clone())),
+            archived: false,
+            announcement: false,
+            slow_mode_ms: 0,
+            last_posts: Map::new(last_posts_key_from_hash(channel_hash.clone())),
+            retention: RetentionPolicy::default(),
+            gate: None,
+            bans: Map::new(bans_key_from_hash(channel_hash.clone())),
+            mutes: Map::new(mutes_key_from_hash(channel_hash.clone())),
+            reports: Map::new(reports_key_from_hash(channel_hash.clone())),
+            report_threshold: 3,
+            min_reputation: 0,
+            tips_received: Map::new(tips_received_key_from_hash(channel_hash.clone())),
+            top_tipped: Leaderboard::default(),
+            subscription: None,
+            subscriptions: Map::new(subscriptions_key_from_hash(channel_hash.clone())),
+            num_pruned_messages: 0,
+            history_commitments: Vector::new(history_commitments_key_from_hash(channel_hash.clone())),
+            chain_links: Vector::new(chain_links_key_from_hash(channel_hash.clone())),
+            daily_activity: Map::new(daily_activity_key_from_hash(channel_hash.clone())),
+            num_messages_by_sender: Map::new(num_messages_by_sender_key_from_hash(channel_hash.clone())),
+            top_posters: Leaderboard::default(),
+            topic: String::new(),
+            description: String::new(),
+            tags: Vec::new(),
+            receivers: Vec::new(),
+            created_at: env::block_timestamp() / 1000000,
+            last_message_time: 0,
+            kind: ChannelKind::Public,
+            members: Map::new(members_key_from_hash(channel_hash)),
+            channel_id,
+        }
+    }
+
+    pub fn into_info(self) -> ChannelInfo {
+        ChannelInfo {
+            kind: self.kind,
+            num_members: self.members.len(),
+            num_messages: self.messages.len(),
+            archived: self.archived,
+            channel_id: self.channel_id,
+        }
+    }
+
+    /// Appends a new message to the channel and returns its index.
+    pub fn add_message(&mut self, message: Message) -> u64 {
+        let message_index = self.messages.len();
+        if let Some(root_index) = message.reply_to {
+            assert!(root_index < message_index, "The message to reply to doesn't exist");
+            let mut replies = self.threads.get(&root_index).unwrap_or_else(|| {
+                Vector::new(replies_key_from_hash(env::sha256(self.channel_id.as_bytes()), root_index))
+            });
+            replies.push(&message_index);
+            self.threads.insert(&root_index, &replies);
+        }
+        if let Some(muted_until) = self.mutes.get(&message.sender_id) {
+            assert!(message.time >= muted_until, "The account is muted in the channel");
+            self.mutes.remove(&message.sender_id);
+        }
+        if self.slow_mode_ms > 0 && !self.is_moderator(&message.sender_id) {
+            if let Some(last_time) = self.last_posts.get(&message.sender_id) {
+                assert!(
+                    message.time >= last_time + self.slow_mode_ms,
+                    "Slow mode is enabled, wait before posting again"
+                );
+            }
+            self.last_posts.insert(&message.sender_id, &message.time);
+        }
+        self.last_message_time = message.time;
+        let day = message.time / MS_PER_DAY;
+        self.daily_activity.insert(&day, &(self.daily_activity.get(&day).unwrap_or(0) + 1));
+        let num_messages = self.num_messages_by_sender.get(&message.sender_id).unwrap_or(0) + 1;
+        self.num_messages_by_sender.insert(&message.sender_id, &num_messages);
+        self.top_posters.record(&message.sender_id, u128::from(num_messages));
+        let mut link = self.chain_head().unwrap_or_default();
+        link.extend(message.try_to_vec().unwrap());
+        self.chain_links.push(&env::sha256(&link));
+        self.messages.push(&VersionedMessage::V1(message));
+        message_index
+    }
+
+    /// Returns the message upgraded to the current layout.
+    pub fn message(&self, message_index: u64) -> Option<Message> {
+        self.messages.get(message_index).map(Message::from)
+    }
+
+    /// Stores the message in the current layout.
+    pub fn save_message(&mut self, message_index: u64, message: Message) {
+        self.messages.replace(message_index, &VersionedMessage::V1(message));
+    }
+
+    /// Returns the chain link of the last message.
+    pub fn chain_head(&self) -> Option<Vec<u8>> {
+        self.chain_links.len().checked_sub(1).and_then(|index| self.chain_links.get(index))
+    }
+
+    /// Replaces up to `limit` of the oldest messages that exceed the retention policy with
+    /// tombstones and records a `HistoryCommitment` over them. Returns the storage freed by every
+    /// pruned message.
+    pub fn prune(&mut self, time: u64, limit: u64) -> Vec<FreedStorage> {
+        let from_index = self.num_pruned_messages;
+        let mut leaves = Vec::new();
+        let mut pruned = Vec::new();
+        while (pruned.len() as u64) < limit && self.num_pruned_messages < self.messages.len() {
+            let message_index = self.num_pruned_messages;
+            let message = self.message(message_index).unwrap();
+            let too_many = self
+                .retention
+                .max_messages
+                .map(|max_messages| self.messages.len() - message_index > max_messages)
+                .unwrap_or(false);
+            let too_old = self.retention.max_age_ms.map(|max_age_ms| message.time + max_age_ms < time).unwrap_or(false);
+            if !too_many && !too_old {
+                break;
+            }
+            leaves.push(env::sha256(&message.try_to_vec().unwrap()));
+            pruned.push(self.tombstone_message(message_index, message));
+            self.num_pruned_messages += 1;
+        }
+        if !leaves.is_empty() {
+            self.history_commitments.push(&HistoryCommitment {
+                from_index,
+                to_index: self.num_pruned_messages,
+                root: hex_encode(&merkle_root(leaves)),
+                time,
+            });
+        }
+        pruned
+    }
+
+    /// Replaces the message with a tombstone.
+    fn tombstone_message(&mut self, message_index: u64, mut message: Message) -> FreedStorage {
+        let initial_storage_usage = env::storage_usage();
+        if let Some(mut revisions) = self.revisions.remove(&message_index) {
+            revisions.clear();
+        }
+        message.tombstone();
+        let account_id = message.storage_payer_id.clone().unwrap_or_else(env::current_account_id);
+        self.save_message(message_index, message);
+        FreedStorage {
+            account_id,
+            num_bytes: initial_storage_usage.saturating_sub(env::storage_usage()),
+        }
+    }
+
+    /// Returns the number of messages posted at or after the given time.
+    pub fn num_messages_since(&self, time: u64) -> u64 {
+        self.messages.len() - self.first_message_since(time)
+    }
+
+    /// Returns the index of the first message posted at or after the time. Messages are appended
+    /// in time order, so the index is found with a binary search.
+    pub fn first_message_since(&self, time: u64) -> u64 {
+        let (mut low, mut high) = (0, self.messages.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.message(middle).unwrap().time < time {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    pub fn edit_message(&mut self, message_index: u64, sender_id: &AccountId, new_body: MessageBody, time: u64) {
+        let mut message = self.message(message_index).expect("Message doesn't exist");
+        assert_eq!(&message.sender_id, sender_id, "Only the sender can edit the message");
+        assert!(!message.deleted, "The message is deleted");
+        let mut revisions = self.revisions.get(&message_index).unwrap_or_else(|| {
+            Vector::new(message_revisions_key_from_hash(env::sha256(self.channel_id.as_bytes()), message_index))
+        });
+        revisions.push(&Revision {
+            time: message.edited_at.unwrap_or(message.time),
+            body: std::mem::replace(&mut message.body, new_body),
+        });
+        self.revisions.insert(&message_index, &revisions);
+        message.edited_at = Some(time);
+        self.save_message(message_index, message);
+    }
+
+    pub fn delete_message(&mut self, message_index: u64, sender_id: &AccountId) -> FreedStorage {
+        let message = self.message(message_index).expect("Message doesn't exist");
+        assert_eq!(&message.sender_id, sender_id, "Only the sender can delete the message");
+        assert!(!message.deleted, "The message is already deleted");
+        self.tombstone_message(message_index, message)
+    }
+
+    /// Returns whether the account can manage the channel. The contract account can manage every
+    /// channel.
+    pub fn is_owner(&self, account_id: &AccountId) -> bool {
+        account_id == &self.owner_id || account_id == &env::current_account_id()
+    }
+
+    pub fn assert_owner(&self, account_id: &AccountId) {
+        assert!(self.is_owner(account_id), "Only the channel owner can do this");
+    }
+
+    /// Returns whether the account can read and post messages in the channel.
+    pub fn can_access(&self, account_id: &AccountId) -> bool {
+        match self.kind {
+            ChannelKind::Public => true,
+            ChannelKind::Private | ChannelKind::Direct | ChannelKind::Group => {
+                self.is_owner(account_id)
+                    || (self.is_member(account_id) && (self.is_moderator(account_id) || self.is_subscribed(account_id)))
+            },
+        }
+    }
+
+    pub fn is_member(&self, account_id: &AccountId) -> bool {
+        self.members.get(account_id).is_some()
+    }
+
+    /// Returns whether the account has an active subscription or the channel doesn't require one.
+    pub fn is_subscribed(&self, account_id: &AccountId) -> bool {
+        self.subscription.is_none()
+            || self
+                .subscriptions
+                .get(account_id)
+                .map(|expires_at| expires_at > env::block_timestamp() / 1000000)
+                .unwrap_or(false)
+    }
+
+    pub fn is_banned(&self, account_id: &AccountId) -> bool {
+        self.bans.get(account_id).is_some()
+    }
+
+    pub fn assert_not_archived(&self) {
+        assert!(!self.archived, "The channel is archived");
+    }
+
+    /// Asserts that the account can post new messages to the channel.
+    pub fn assert_can_post(&self, account_id: &AccountId) {
+        assert!(!self.is_banned(account_id), "The account is banned from the channel");
+        self.assert_not_archived();
+        assert!(
+            !self.announcement || self.is_moderator(account_id),
+            "Only the owner and moderators can post to the announcement channel"
+        );
+        assert!(
+            self.gate.is_none() || self.is_member(account_id) || self.is_moderator(account_id),
+            "Only members can post to the gated channel"
+        );
+    }
+
+    /// Returns whether the account can moderate the channel. Channel owners are moderators.
+    pub fn is_moderator(&self, account_id: &AccountId) -> bool {
+        self.is_owner(account_id) || self.moderators.contains(account_id)
+    }
+
+    pub fn moderator_delete_message(
+        &mut self,
+        message_index: u64,
+        moderator_id: &AccountId,
+        reason: String,
+        time: u64,
+    ) -> FreedStorage {
+        assert!(self.is_moderator(moderator_id), "Only moderators can remove messages");
+        let message = self.message(message_index).expect("Message doesn't exist");
+        assert!(!message.deleted, "The message is already deleted");
+        let freed_storage = self.tombstone_message(message_index, message);
+        self.reports.remove(&message_index);
+        self.moderation_log.push(&ModerationLogEntry {
+            time,
+            moderator_id: moderator_id.clone(),
+            message_index,
+            reason,
+        });
+        freed_storage
+    }
+
+    /// Returns the author of the message the sender wants to tip.
+    pub fn tipped_message_author(&self, message_index: u64, sender_id: &AccountId) -> AccountId {
+        let message = self.message(message_index).expect("Message doesn't exist");
+        assert!(!message.deleted, "The message is deleted");
+        assert_ne!(&message.sender_id, sender_id, "Can't tip your own message");
+        message.sender_id
+    }
+
+    /// Adds the report to the message and hides the message once it reaches the report threshold.
+    pub fn report_message(&mut self, message_index: u64, report: Report) {
+        let mut message = self.message(message_index).expect("Message doesn't exist");
+        assert!(!message.deleted, "The message is deleted");
+        let mut reports = self.reports.get(&message_index).unwrap_or_default();
+        assert!(
+            reports.iter().all(|other| other.reporter_id != report.reporter_id),
+            "The message is already reported by the account"
+        );
+        reports.push(report);
+        if self.report_threshold > 0 && reports.len() as u64 >= self.report_threshold && !message.hidden {
+            message.hidden = true;
+            self.save_message(message_index, message);
+        }
+        self.reports.insert(&message_index, &reports);
+    }
+
+    /// Closes the reports of the message and shows it again.
+    pub fn restore_message(&mut self, message_index: u64) {
+        self.reports.remove(&message_index);
+        let mut message = self.message(message_index).expect("Message doesn't exist");
+        if message.hidden {
+            message.hidden = false;
+            self.save_message(message_index, message);
+        }
+    }
+}
+
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+
+    fn alice() -> String {
+        "alice.near".to_string()
+    }
+
+    fn bob() -> String {
+        "bob.near".to_string()
+    }
+
+    fn carol() -> String {
+        "carol.near".to_string()
+    }
+
+    fn get_context(signer_account_pk: Vec<u8>) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: alice(),
+            signer_account_pk,
+            predecessor_account_id: alice(),
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 10u128.pow(26),
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 10u128.pow(24),
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+        }
+    }
+    /// Like `testing_env!`, but with the given result of the promise the method is called back on.
+    fn testing_env_with_promise_result(context: VMContext, promise_result: PromiseResult) {
+        let storage = env::take_blockchain_interface().unwrap().as_mut_mocked_blockchain().unwrap().take_storage();
+        env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+            context,
+            Default::default(),
+            Default::default(),
+            vec![promise_result],
+            storage,
+        )));
+    }
+
+    fn set_predecessor(context: &mut VMContext, account_id: String) {
+        context.predecessor_account_id = account_id.clone();
+        context.signer_account_id = account_id;
+    }
+
+    fn bootstrap_channels(channel_ids: &[&str]) -> Option<Vec<BootstrapChannel>> {
+        Some(
+            channel_ids
+                .iter()
+                .map(|channel_id| BootstrapChannel { channel_id: channel_id.to_string(), topic: String::new() })
+                .collect(),
+        )
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    /// Returns the base64-encoded signature of the payload and public key of `signing_key`.
+    fn sign_payload(payload: &str) -> (String, String) {
+        let signing_key = signing_key();
+        let signature = signing_key.sign(payload.as_bytes());
+        (base64::encode(&signature.to_bytes()[..]), base64::encode(signing_key.verifying_key().as_bytes()))
+    }
+
+    /// Registers the key of `signing_key` as the signing key of the predecessor.
+    fn set_signing_key(contract: &mut MetanearChat) {
+        let public_key = base64::encode(signing_key().verifying_key().as_bytes());
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"SetSigningKey": {{"public_key": "{}"}}}}"#, public_key),
+        );
+    }
+
+    fn text_body(text: &str) -> String {
+        format!(r#"{{"Text": {{"text": "{}"}}}}"#, text)
+    }
+
+    fn chat_message(channel_id: &str, text: &str) -> String {
+        format!(r#"{{"ChatMessage": {{"channel_id": "{}", "body": {}}}}}"#, channel_id, text_body(text))
+    }
+
+    fn edit_message(channel_id: &str, message_index: u64, text: &str) -> String {
+        format!(
+            r#"{{"EditMessage": {{"channel_id": "{}", "message_index": {}, "new_body": {}}}}}"#,
+            channel_id,
+            message_index,
+            text_body(text)
+        )
+    }
+
+    #[test]
+    fn test_edit_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hey"));
+
+        set_predecessor(&mut context, alice());
+        context.block_timestamp = 5_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), edit_message("general", 0, "hi"));
+
+        let message = contract.get_channel("general".to_string()).message(0).unwrap();
+        assert_eq!(serde_json::to_value(&message.body).unwrap(), serde_json::json!({"Text": {"text": "hi"}}));
+        assert_eq!(message.edited_at, Some(5));
+        let message = contract.get_channel("general".to_string()).message(1).unwrap();
+        assert_eq!(serde_json::to_value(&message.body).unwrap(), serde_json::json!({"Text": {"text": "hey"}}));
+        assert_eq!(message.edited_at, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the sender can edit the message")]
+    fn test_edit_message_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), edit_message("general", 0, "hi"));
+    }
+
+    #[test]
+    fn test_delete_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hey"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"DeleteMessage": {"channel_id": "general", "message_index": 1}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["deleted"], false);
+        assert_eq!(response["messages"][0]["body"]["Text"]["text"], "hello");
+        assert_eq!(response["messages"][1]["deleted"], true);
+        assert_eq!(response["messages"][1]["body"]["Text"]["text"], "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the sender can delete the message")]
+    fn test_delete_message_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"DeleteMessage": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_post_message_result() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        context.block_timestamp = 7_000_000;
+        testing_env!(context);
+        let result = contract.post_message("chat".to_string(), chat_message("general", "hey"));
+        assert_eq!(result.channel_id, "general");
+        assert_eq!(result.message_index, 1);
+        assert_eq!(result.time, 7);
+    }
+
+    #[test]
+    fn test_proxy_post_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_trusted_proxy(bob());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.proxy_post_message("chat".to_string(), carol(), chat_message("general", "hello"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["sender_id"], "carol.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only trusted proxy contracts can post on behalf of other accounts")]
+    fn test_proxy_post_message_by_untrusted_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.proxy_post_message("chat".to_string(), carol(), chat_message("general", "hello"));
+    }
+
+    #[test]
+    fn test_bridge_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_trusted_peer("peer.near".to_string());
+
+        set_predecessor(&mut context, "peer.near".to_string());
+        testing_env!(context);
+        let body: MessageBody = serde_json::from_str(&text_body("hello")).unwrap();
+        contract.bridge_message("general".to_string(), carol(), body, vec![], "lobby".to_string(), 7);
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["sender_id"], "carol.near");
+        assert_eq!(
+            response["messages"][0]["bridged_from"],
+            serde_json::json!({"contract_id": "peer.near", "channel_id": "lobby", "message_index": 7})
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only trusted peer contracts can bridge messages")]
+    fn test_bridge_message_from_untrusted_contract() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, "peer.near".to_string());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        let body: MessageBody = serde_json::from_str(&text_body("hello")).unwrap();
+        contract.bridge_message("general".to_string(), carol(), body, vec![], "lobby".to_string(), 7);
+    }
+
+    #[test]
+    fn test_thread_messages() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "question"));
+        contract.post_message("chat".to_string(), chat_message("general", "unrelated"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let reply = format!(
+            r#"{{"ReplyMessage": {{"channel_id": "general", "reply_to": 0, "body": {}}}}}"#,
+            text_body("answer")
+        );
+        contract.post_message("chat".to_string(), reply.clone());
+        contract.post_message("chat".to_string(), reply);
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ThreadMessages": {"channel_id": "general", "root_index": 0, "from_index": 1, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_replies"], 2);
+        assert_eq!(response["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(response["messages"][0]["index"], 3);
+        assert_eq!(response["messages"][0]["reply_to"], 0);
+        assert_eq!(response["messages"][0]["sender_id"], "bob.near");
+
+        let response = contract.get_channel_messages("general".to_string(), 0, 2);
+        assert_eq!(response.messages[0].reply_count, Some(2));
+        assert_eq!(response.messages[0].last_reply_time, Some(response.messages[1].message.time));
+        assert_eq!(response.messages[1].reply_count, None);
+    }
+
+    #[test]
+    fn test_reactions() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        let react = r#"{"React": {"channel_id": "general", "message_index": 0, "emoji": "👍"}}"#;
+        contract.post_message("chat".to_string(), react.to_string());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), react.to_string());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"React": {"channel_id": "general", "message_index": 0, "emoji": "🎉"}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Unreact": {"channel_id": "general", "message_index": 0, "emoji": "🎉"}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["messages"][0]["reactions"],
+            serde_json::json!([{"emoji": "👍", "count": 2}])
+        );
+
+        let request = r#"{"ReactionsBy": {"account_id": "bob.near", "channel_id": "general"}}"#;
+        assert_eq!(
+            contract.get("chat".to_string(), request.to_string()).unwrap(),
+            r#"{"reactions":{"0":["👍"]}}"#
+        );
+        let request = r#"{"ReactionsBy": {"account_id": "carol.near", "channel_id": "general"}}"#;
+        assert_eq!(contract.get("chat".to_string(), request.to_string()).unwrap(), r#"{"reactions":{}}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "Already reacted with this emoji")]
+    fn test_react_twice() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        let react = r#"{"React": {"channel_id": "general", "message_index": 0, "emoji": "👍"}}"#;
+        contract.post_message("chat".to_string(), react.to_string());
+        contract.post_message("chat".to_string(), react.to_string());
+    }
+
+    #[test]
+    fn test_message_history() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.block_timestamp = 1_000_000;
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "helo"));
+        for (time, text) in &[(2_000_000, "hello"), (3_000_000, "hello!")] {
+            context.block_timestamp = *time;
+            testing_env!(context.clone());
+            contract.post_message("chat".to_string(), edit_message("general", 0, text));
+        }
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"MessageHistory": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["revisions"],
+            serde_json::json!([
+                {"time": 1, "body": {"Text": {"text": "helo"}}},
+                {"time": 2, "body": {"Text": {"text": "hello"}}},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_message_history_of_deleted_message() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "helo"));
+        contract.post_message("chat".to_string(), edit_message("general", 0, "hello"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"DeleteMessage": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"MessageHistory": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        ).unwrap();
+        assert_eq!(response, r#"{"revisions":[]}"#);
+    }
+
+    #[test]
+    fn test_message_history_of_hidden_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "helo"));
+        contract.post_message("chat".to_string(), edit_message("general", 0, "hello"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReportThreshold": {"channel_id": "general", "report_threshold": 1}}"#.to_string(),
+        );
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Report": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"MessageHistory": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        ).unwrap();
+        assert_eq!(response, r#"{"revisions":[]}"#);
+    }
+
+    #[test]
+    fn test_typed_message_bodies() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"Image": {
+                "cid": "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG",
+                "mime": "image/png"
+            }}}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"Link": {
+                "url": "https://metanear.com",
+                "title": "Meta NEAR"
+            }}}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["body"]["Image"]["mime"], "image/png");
+        assert_eq!(response["messages"][1]["body"]["Link"]["url"], "https://metanear.com");
+    }
+
+    #[test]
+    #[should_panic(expected = "URL should start with `http://` or `https://`")]
+    fn test_invalid_link() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"Link": {
+                "url": "javascript:alert(1)",
+                "title": ""
+            }}}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract can post system messages")]
+    fn test_system_message_from_user() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"System": {"text": "hi"}}}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_attachments() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"Text": {"text": "report"}}, "attachments": [{
+                "cid": "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG",
+                "mime": "application/pdf",
+                "size": 1024
+            }]}}"#.to_string(),
+        );
+
+        let message = contract.get_channel("general".to_string()).message(0).unwrap();
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].size, 1024);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attachment is too large")]
+    fn test_attachment_too_large() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"Text": {"text": "movie"}}, "attachments": [{
+                "cid": "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG",
+                "mime": "video/mp4",
+                "size": 1000000000
+            }]}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_forward_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.block_timestamp = 1_000_000;
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "news"));
+
+        set_predecessor(&mut context, bob());
+        context.block_timestamp = 2_000_000;
+        testing_env!(context);
+        let result = contract.post_message(
+            "chat".to_string(),
+            r#"{"Forward": {"from_channel_id": "general", "message_index": 0, "to_channel_id": "random"}}"#.to_string(),
+        );
+        assert_eq!(result.channel_id, "random");
+        assert_eq!(result.message_index, 0);
+
+        let message = contract.get_channel("random".to_string()).message(0).unwrap();
+        assert_eq!(message.sender_id, bob());
+        assert_eq!(message.time, 2);
+        let forwarded_from = message.forwarded_from.unwrap();
+        assert_eq!(forwarded_from.channel_id, "general");
+        assert_eq!(forwarded_from.message_index, 0);
+        assert_eq!(forwarded_from.sender_id, alice());
+        assert_eq!(forwarded_from.time, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "System messages can't be forwarded")]
+    fn test_forward_system_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ChatMessage": {"channel_id": "general", "body": {"System": {"text": "Maintenance"}}}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Forward": {"from_channel_id": "general", "message_index": 0, "to_channel_id": "random"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The message is hidden")]
+    fn test_forward_hidden_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReportThreshold": {"channel_id": "general", "report_threshold": 1}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Report": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Forward": {"from_channel_id": "general", "message_index": 0, "to_channel_id": "random"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The message is hidden")]
+    fn test_forward_blocked_sender() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), r#"{"Block": {"account_id": "alice.near"}}"#.to_string());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Forward": {"from_channel_id": "general", "message_index": 0, "to_channel_id": "random"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The message contains a banned word")]
+    fn test_forward_banned_word() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "buy crypto"));
+        contract.add_banned_word("crypto".to_string());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Forward": {"from_channel_id": "general", "message_index": 0, "to_channel_id": "random"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Message text is too long")]
+    fn test_max_message_len() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_message_len: 4,
+            ..Config::default()
+        });
+        contract.post_message("chat".to_string(), chat_message("general", "hey"));
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can do this")]
+    fn test_update_config_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.update_config(Config::default());
+    }
+
+    #[test]
+    fn test_max_page_size() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_page_size: 2,
+            ..Config::default()
+        });
+        assert_eq!(contract.get_config().max_page_size, 2);
+        for text in &["a", "b", "c"] {
+            contract.post_message("chat".to_string(), chat_message("general", text));
+        }
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(response["total"], 3);
+        assert_eq!(response["has_more"], true);
+    }
+
+    #[test]
+    fn test_default_page_size() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            default_page_size: 2,
+            ..Config::default()
+        });
+        for text in &["a", "b", "c"] {
+            contract.post_message("chat".to_string(), chat_message("general", text));
+        }
+        let response = contract.get_channel_messages("general".to_string(), 0, 0);
+        assert_eq!(response.messages.len(), 2);
+        assert!(response.has_more);
+        let response = contract.get_channel_messages("general".to_string(), 2, 0);
+        assert_eq!(response.messages.len(), 1);
+        assert!(!response.has_more);
+    }
+
+    #[test]
+    #[should_panic(expected = "Default page size should be positive")]
+    fn test_zero_default_page_size() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            default_page_size: 0,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Channel ID length is out of the configured bounds")]
+    fn test_max_channel_id_len() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_channel_id_len: 4,
+            ..Config::default()
+        });
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+    }
+
+    #[test]
+    fn test_banned_words() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_banned_word("Spam".to_string());
+        contract.add_banned_word("scam".to_string());
+        contract.remove_banned_word("SCAM".to_string());
+        assert_eq!(contract.get_banned_words(), vec!["spam".to_string()]);
+        contract.post_message("chat".to_string(), chat_message("general", "not a scam"));
+    }
+
+    #[test]
+    fn test_poll() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreatePoll": {"channel_id": "general", "question": "Lunch?", "options": ["pizza", "sushi"], "closes_at": 1000}}"#
+                .to_string(),
+        );
+        let vote = r#"{"Vote": {"channel_id": "general", "message_index": 0, "option": 1}}"#;
+        contract.post_message("chat".to_string(), vote.to_string());
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), vote.to_string());
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["body"]["Poll"]["question"], "Lunch?");
+        assert_eq!(response["messages"][0]["poll_votes"], serde_json::json!([0, 2]));
+    }
+
+    #[test]
+    fn test_weighted_poll() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreatePoll": {"channel_id": "general", "question": "Fund it?", "options": ["yes", "no"], "closes_at": 1000,
+                "weight_token_id": "token.near"}}"#.to_string(),
+        );
+        for account_id in &[bob(), carol()] {
+            set_predecessor(&mut context, account_id.clone());
+            testing_env!(context.clone());
+            contract.vote_weighted("general".to_string(), 0, 1);
+        }
+
+        set_predecessor(&mut context, alice());
+        testing_env_with_promise_result(context.clone(), PromiseResult::Successful(b"\"0\"".to_vec()));
+        assert!(!contract.on_vote_weight("general".to_string(), 0, bob(), 1));
+        testing_env_with_promise_result(context.clone(), PromiseResult::Successful(b"\"250\"".to_vec()));
+        assert!(contract.on_vote_weight("general".to_string(), 0, carol(), 1));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["poll_votes"], serde_json::json!([0, 1]));
+        assert_eq!(response["messages"][0]["poll_weights"], serde_json::json!(["0", "250"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Weighted polls are voted with `vote_weighted`")]
+    fn test_vote_in_weighted_poll() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreatePoll": {"channel_id": "general", "question": "Fund it?", "options": ["yes", "no"], "closes_at": 1000,
+                "weight_token_id": "token.near"}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Vote": {"channel_id": "general", "message_index": 0, "option": 0}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Already voted in this poll")]
+    fn test_vote_twice() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreatePoll": {"channel_id": "general", "question": "Lunch?", "options": ["pizza", "sushi"], "closes_at": 1000}}"#
+                .to_string(),
+        );
+        let vote = r#"{"Vote": {"channel_id": "general", "message_index": 0, "option": 0}}"#;
+        contract.post_message("chat".to_string(), vote.to_string());
+        contract.post_message("chat".to_string(), vote.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The poll is closed")]
+    fn test_vote_after_poll_closes() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreatePoll": {"channel_id": "general", "question": "Lunch?", "options": ["pizza", "sushi"], "closes_at": 1000}}"#
+                .to_string(),
+        );
+        context.block_timestamp = 1000 * 1000000;
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Vote": {"channel_id": "general", "message_index": 0, "option": 0}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_encrypted_message() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_banned_word("spam".to_string());
+        let body = r#"{"Encrypted": {"ciphertext_b64": "c3BhbQ==", "key_ids": ["bob.near#1"]}}"#;
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"ChatMessage": {{"channel_id": "general", "body": {}}}}}"#, body),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["body"], serde_json::from_str::<serde_json::Value>(body).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Ciphertext is too long")]
+    fn test_encrypted_message_too_long() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        let body = format!(r#"{{"Encrypted": {{"ciphertext_b64": "{}", "key_ids": []}}}}"#, "A".repeat(4100));
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"ChatMessage": {{"channel_id": "general", "body": {}}}}}"#, body),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many key IDs")]
+    fn test_encrypted_message_too_many_key_ids() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        let key_ids = serde_json::to_string(&vec!["bob.near#1"; 51]).unwrap();
+        let body = format!(r#"{{"Encrypted": {{"ciphertext_b64": "c3BhbQ==", "key_ids": {}}}}}"#, key_ids);
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"ChatMessage": {{"channel_id": "general", "body": {}}}}}"#, body),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The message contains a banned word")]
+    fn test_post_banned_word() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_banned_word("spam".to_string());
+        contract.post_message("chat".to_string(), chat_message("general", "Buy SPAM now"));
+    }
+
+    #[test]
+    fn test_contract_metadata() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_message_len: 4,
+            ..Config::default()
+        });
+        let metadata = serde_json::to_value(contract.contract_metadata()).unwrap();
+        assert_eq!(metadata["standard"], "metanear-chat");
+        assert_eq!(metadata["config"]["max_message_len"], 4);
+        assert!(metadata["get_requests"].as_array().unwrap().contains(&serde_json::json!("ChannelMessages")));
+        assert!(metadata["incoming_messages"].as_array().unwrap().contains(&serde_json::json!("ChatMessage")));
+    }
+
+    #[test]
+    fn test_extract_mentions() {
+        assert_eq!(
+            extract_mentions("@alice.near, @bob.near and @alice.near. Email me at @x or me@carol.near."),
+            vec!["alice.near".to_string(), "bob.near".to_string(), "carol.near".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_mentions() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hi @bob.near"));
+        contract.post_message("chat".to_string(), chat_message("random", "hi"));
+        contract.post_message("chat".to_string(), chat_message("random", "@bob.near @carol.near hey"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("random", "I'm @bob.near"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"Mentions": {"account_id": "bob.near", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_mentions"], 2);
+        assert_eq!(response["messages"][0]["channel_id"], "general");
+        assert_eq!(response["messages"][0]["index"], 0);
+        assert_eq!(response["messages"][1]["channel_id"], "random");
+        assert_eq!(response["messages"][1]["index"], 1);
+    }
+
+    #[test]
+    fn test_unread_counts() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        for _ in 0..3 {
+            contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        }
+        contract.post_message("chat".to_string(), chat_message("random", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"MarkRead": {"channel_id": "general", "up_to_index": 0}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"UnreadCounts": {"account_id": "bob.near", "channel_ids": ["general", "random"]}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["unread_counts"],
+            serde_json::json!([
+                {"channel_id": "general", "last_read_index": 0, "num_unread": 2},
+                {"channel_id": "random", "last_read_index": null, "num_unread": 1},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_channel_messages_reverse() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        for text in &["one", "two", "three", "four"] {
+            contract.post_message("chat".to_string(), chat_message("general", text));
+        }
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessagesReverse": {"channel_id": "general", "from_end": 1, "limit": 2}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_messages"], 4);
+        assert_eq!(response["messages"][0]["index"], 2);
+        assert_eq!(response["messages"][0]["body"]["Text"]["text"], "three");
+        assert_eq!(response["messages"][1]["index"], 1);
+        assert_eq!(response["messages"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_messages_by_indices() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        for text in &["one", "two", "three"] {
+            contract.post_message("chat".to_string(), chat_message("general", text));
+        }
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"MessagesByIndices": {"channel_id": "general", "indices": [2, 5, 0]}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(response["messages"][0]["index"], 2);
+        assert_eq!(response["messages"][0]["body"]["Text"]["text"], "three");
+        assert_eq!(response["messages"][1]["index"], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many message indices")]
+    fn test_too_many_message_indices() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_page_size: 2,
+            ..Config::default()
+        });
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.get(
+            "chat".to_string(),
+            r#"{"MessagesByIndices": {"channel_id": "general", "indices": [0, 0, 0]}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_post_messages() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        let results = contract.post_messages(
+            "chat".to_string(),
+            vec![
+                chat_message("general", "one"),
+                chat_message("random", "two"),
+                chat_message("general", "three"),
+            ],
+        );
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2].channel_id, "general");
+        assert_eq!(results[2].message_index, 1);
+        assert_eq!(contract.total_num_messages, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many messages in the batch")]
+    fn test_post_messages_too_many() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_batch_size: 1,
+            ..Config::default()
+        });
+        contract.post_messages(
+            "chat".to_string(),
+            vec![chat_message("general", "one"), chat_message("general", "two")],
+        );
+    }
+
+    #[test]
+    fn test_message_envelope() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        let message = r#"{"type": "ChatMessage", "payload": {"channel_id": "general", "body": {"Text": {"text": "hi"}}}}"#;
+        let result = contract.post_message("chat".to_string(), message.to_string());
+        assert_eq!(serde_json::to_value(&result).unwrap()["channel_id"], "general");
+
+        let results = contract.post_messages(
+            "chat".to_string(),
+            vec![r#"{"type": "FutureMessage", "payload": {}}"#.to_string(), chat_message("general", "hello")],
+        );
+        assert_eq!(serde_json::to_value(&results[0]).unwrap()["channel_id"], "");
+        assert_eq!(serde_json::to_value(&results[1]).unwrap()["message_index"], 1);
+        assert_eq!(contract.get_channel("general".to_string()).messages.len(), 2);
+    }
+
+    #[test]
+    fn test_rejected_message_event_log() {
+        let sender_id = alice();
+        assert_eq!(
+            event_log(ChatEvent::MessageRejected(vec![RejectedMessageEventData {
+                sender_id: &sender_id,
+                message_type: "FutureMessage",
+                reason: "Unknown message type",
+            }])),
+            r#"EVENT_JSON:{"standard":"metanear-chat","version":"1.0.0","event":"message_rejected","data":[{"sender_id":"alice.near","message_type":"FutureMessage","reason":"Unknown message type"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_app_inbox() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        let result = contract.post_message("mail".to_string(), r#"{"subject": "hi"}"#.to_string());
+        assert_eq!(serde_json::to_value(&result).unwrap()["channel_id"], "");
+
+        let inbox = contract.get_app_inbox("mail".to_string(), 0, 10);
+        assert_eq!(inbox.num_messages, 1);
+        assert_eq!(inbox.messages[0].sender_id, bob());
+        assert_eq!(inbox.messages[0].message, r#"{"subject": "hi"}"#);
+        assert_eq!(contract.get_app_inbox("chat".to_string(), 0, 10).num_messages, 0);
+    }
+
+    #[test]
+    fn test_event_log() {
+        let channel_id = "general".to_string();
+        let sender_id = alice();
+        assert_eq!(
+            event_log(ChatEvent::MessagePosted(vec![MessageEventData {
+                channel_id: &channel_id,
+                message_index: 3,
+                sender_id: &sender_id,
+            }])),
+            r#"EVENT_JSON:{"standard":"metanear-chat","version":"1.0.0","event":"message_posted","data":[{"channel_id":"general","message_index":3,"sender_id":"alice.near"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_moderator_delete() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_moderator("general".to_string(), bob());
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ModDelete": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+        );
+
+        assert!(contract.get_channel("general".to_string()).message(0).unwrap().deleted);
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ModerationLog": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_entries"], 1);
+        assert_eq!(response["entries"][0]["moderator_id"], "bob.near");
+        assert_eq!(response["entries"][0]["reason"], "spam");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only moderators can remove messages")]
+    fn test_moderator_delete_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ModDelete": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_report_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReportThreshold": {"channel_id": "general", "report_threshold": 2}}"#.to_string(),
+        );
+
+        for reporter_id in &[bob(), carol()] {
+            set_predecessor(&mut context, reporter_id.clone());
+            testing_env!(context.clone());
+            contract.post_message(
+                "chat".to_string(),
+                r#"{"Report": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+            );
+        }
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ModerationQueue": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_reported_messages"], 1);
+        assert_eq!(response["messages"][0]["hidden"], true);
+        assert_eq!(response["messages"][0]["reports"][1]["reporter_id"], "carol.near");
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["redacted"], true);
+
+        set_predecessor(&mut context, alice());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ResolveReport": {"channel_id": "general", "message_index": 0, "remove": false}}"#.to_string(),
+        );
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["body"]["Text"]["text"], "hello");
+        assert_eq!(contract.get_channel("general".to_string()).reports.len(), 0);
+    }
+
+    #[test]
+    fn test_report_penalty() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        for message_index in 0..2 {
+            contract.post_message(
+                "chat".to_string(),
+                format!(
+                    r#"{{"Report": {{"channel_id": "general", "message_index": {}, "reason": "spam"}}}}"#,
+                    message_index
+                ),
+            );
+        }
+
+        set_predecessor(&mut context, alice());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ResolveReport": {"channel_id": "general", "message_index": 0, "remove": false}}"#.to_string(),
+        );
+        let response = contract.get("chat".to_string(), r#"{"Reputation": {"account_id": "bob.near"}}"#.to_string());
+        let response: serde_json::Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert_eq!(response["num_reports"], 0);
+        assert_eq!(response["score"], 2);
+
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ResolveReport": {"channel_id": "general", "message_index": 1, "remove": true}}"#.to_string(),
+        );
+        let response = contract.get("chat".to_string(), r#"{"Reputation": {"account_id": "bob.near"}}"#.to_string());
+        let response: serde_json::Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert_eq!(response["num_reports"], 1);
+        assert_eq!(response["score"], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only moderators can resolve reports")]
+    fn test_resolve_report_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Report": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ResolveReport": {"channel_id": "general", "message_index": 0, "remove": true}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_global_ban() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+
+        set_predecessor(&mut context, alice());
+        testing_env!(context);
+        contract.ban_account(bob(), "spam".to_string(), true);
+        assert_eq!(contract.get_global_ban(bob()).unwrap().reason, "spam");
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["redacted"], true);
+        assert_eq!(response["messages"][0]["body"]["Text"]["text"], "");
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is banned from the contract")]
+    fn test_post_globally_banned() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.ban_account(bob(), "spam".to_string(), false);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+    }
+
+    #[test]
+    fn test_reputation() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("general", "hello again"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"React": {"channel_id": "general", "message_index": 0, "emoji": "👍"}}"#.to_string(),
+        );
+        let response = contract.get("chat".to_string(), r#"{"Reputation": {"account_id": "bob.near"}}"#.to_string());
+        let response: serde_json::Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert_eq!(response["num_messages"], 2);
+        assert_eq!(response["num_reactions_received"], 1);
+        assert_eq!(response["score"], 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough reputation to post to the channel")]
+    fn test_post_without_min_reputation() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetMinReputation": {"channel_id": "general", "min_reputation": 5}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("random", "hello"));
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+    }
+
+    #[test]
+    fn test_tip_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+        context.attached_deposit = 1000;
+        testing_env!(context);
+        let tip = r#"{"Tip": {"channel_id": "general", "message_index": 0}}"#;
+        contract.post_message("chat".to_string(), tip.to_string());
+        contract.post_message("chat".to_string(), tip.to_string());
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["tips"], "2000");
+
+        for request in &[
+            r#"{"TopTipped": {"channel_id": "general", "limit": 10}}"#,
+            r#"{"TopTipped": {"limit": 10}}"#,
+        ] {
+            let response = contract.get("chat".to_string(), request.to_string()).unwrap();
+            let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(response["accounts"], serde_json::json!([{"account_id": "bob.near", "amount": "2000"}]));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Only one message in the batch can tip the attached deposit")]
+    fn test_tip_twice_in_batch() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let tip = r#"{"Tip": {"channel_id": "general", "message_index": 0}}"#;
+        contract.post_messages("chat".to_string(), vec![tip.to_string(), tip.to_string()]);
+    }
+
+    #[test]
+    fn test_leaderboard() {
+        let mut leaderboard = Leaderboard::default();
+        for total in 1..=(LEADERBOARD_SIZE as u128 + 5) {
+            leaderboard.record(&format!("account{}.near", total), total);
+        }
+        leaderboard.record(&"account1.near".to_string(), 100);
+        leaderboard.record(&"account30.near".to_string(), 50);
+        leaderboard.record(&"account30.near".to_string(), 60);
+
+        assert_eq!(leaderboard.entries.len(), LEADERBOARD_SIZE);
+        let top: Vec<_> = leaderboard.top(3).cloned().collect();
+        assert_eq!(top, vec![
+            ("account1.near".to_string(), 100),
+            ("account30.near".to_string(), 60),
+            ("account25.near".to_string(), 25),
+        ]);
+        assert_eq!(leaderboard.entries.last().unwrap().1, 8);
+    }
+
+    #[test]
+    fn test_top_posters() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "two"));
+        contract.post_message("chat".to_string(), chat_message("general", "three"));
+        contract.post_message("chat".to_string(), chat_message("random", "four"));
+
+        let request = r#"{"TopPosters": {"channel_id": "general", "limit": 10}}"#;
+        let response = contract.get("chat".to_string(), request.to_string()).unwrap();
+        assert_eq!(
+            response,
+            r#"{"accounts":[{"account_id":"bob.near","num_messages":2},{"account_id":"alice.near","num_messages":1}]}"#
+        );
+        let request = r#"{"TopPosters": {"limit": 1}}"#;
+        let response = contract.get("chat".to_string(), request.to_string()).unwrap();
+        assert_eq!(response, r#"{"accounts":[{"account_id":"bob.near","num_messages":3}]}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't tip your own message")]
+    fn test_tip_own_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Tip": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_token_tip() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, "token.near".to_string());
+        testing_env!(context.clone());
+        let msg = r#"{"tip": {"channel_id": "general", "message_index": 0}}"#;
+        assert_eq!(contract.ft_on_transfer(carol(), "100".to_string(), msg.to_string()), "0");
+        contract.ft_on_transfer(carol(), "50".to_string(), msg.to_string());
+        assert_eq!(contract.get_token_balances(bob()).get("token.near").unwrap(), "150");
+
+        set_predecessor(&mut context, bob());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.withdraw_tokens("token.near".to_string(), Some("100".to_string()));
+        assert_eq!(contract.get_token_balances(bob()).get("token.near").unwrap(), "50");
+    }
+
+    #[test]
+    fn test_token_withdrawal_failed() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_token_balance(&bob(), &"token.near".to_string(), 100);
+
+        set_predecessor(&mut context, bob());
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.withdraw_tokens("token.near".to_string(), None);
+        assert_eq!(contract.get_token_balances(bob()).get("token.near").unwrap(), "0");
+
+        set_predecessor(&mut context, alice());
+        testing_env_with_promise_result(context, PromiseResult::Failed);
+        assert!(!contract.on_tokens_withdrawn(bob(), "token.near".to_string(), "100".to_string()));
+        assert_eq!(contract.get_token_balances(bob()).get("token.near").unwrap(), "100");
+    }
+
+    fn subscription_channel(context: &mut VMContext) -> MetanearChat {
+        set_predecessor(context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "club", &[]);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetSubscription": {"channel_id": "club", "subscription": {"price": "1000", "period_ms": 1000}}}"#
+                .to_string(),
+        );
+
+        set_predecessor(context, carol());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), r#"{"JoinChannel": {"channel_id": "club"}}"#.to_string());
+        contract
+    }
+
+    #[test]
+    fn test_subscription_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        let mut contract = subscription_channel(&mut context);
+        contract.post_message("chat".to_string(), chat_message("club", "hello"));
+        assert_eq!(contract.get_channel("club".to_string()).subscriptions.get(&carol()), Some(1000));
+
+        context.block_timestamp = 500_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), r#"{"JoinChannel": {"channel_id": "club"}}"#.to_string());
+        assert_eq!(contract.get_channel("club".to_string()).subscriptions.get(&carol()), Some(2000));
+    }
+
+    #[test]
+    #[should_panic(expected = "No access to the private channel")]
+    fn test_subscription_expired() {
+        let mut context = get_context(vec![0, 1, 2]);
+        let mut contract = subscription_channel(&mut context);
+
+        context.block_timestamp = 1_000_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("club", "hello"));
+    }
+
+    #[test]
+    fn test_withdraw_treasury() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            tip_fee_bps: 1000,
+            ..Config::default()
+        });
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+        context.attached_deposit = 1000;
+        testing_env!(context.clone());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Tip": {"channel_id": "general", "message_index": 0}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, alice());
+        testing_env!(context);
+        contract.withdraw_treasury("40".to_string(), carol());
+        let treasury = contract.get_treasury();
+        assert_eq!(treasury.balance, "60");
+        assert_eq!(treasury.total_collected, "100");
+        assert_eq!(treasury.total_withdrawn, "40");
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount is greater than the treasury balance")]
+    fn test_withdraw_treasury_too_much() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.withdraw_treasury("1".to_string(), alice());
+    }
+
+    #[test]
+    fn test_channels() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.post_message("chat".to_string(), chat_message("random", "two"));
+        contract.post_message("chat".to_string(), chat_message("general", "three"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"Channels": {"from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "num_channels": 2,
+                "channels": [
+                    {"channel_id": "general", "kind": "Public", "num_members": 0, "num_messages": 2, "archived": false},
+                    {"channel_id": "random", "kind": "Public", "num_members": 0, "num_messages": 1, "archived": false},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_archived_channel() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("old", "bye"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetArchived": {"channel_id": "old", "archived": true}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"Channels": {"from_index": 0, "limit": 10, "skip_archived": true}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_channels"], 2);
+        assert_eq!(
+            response["channels"],
+            serde_json::json!([{"channel_id": "general", "kind": "Public", "num_members": 0, "num_messages": 1, "archived": false}])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The channel is archived")]
+    fn test_post_to_archived_channel() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetArchived": {"channel_id": "old", "archived": true}}"#.to_string(),
+        );
+        contract.post_message("chat".to_string(), chat_message("old", "hello"));
+    }
+
+    #[test]
+    fn test_channel_meta() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.block_timestamp = 3_000_000;
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetChannelMeta": {"channel_id": "general", "topic": "Anything", "description": "Talk here"}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelStatus": {"channel_id": "general"}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "owner_id": "alice.near",
+                "kind": "Public",
+                "announcement": false,
+                "num_members": 0,
+                "num_messages": 0,
+                "num_pruned_messages": 0,
+                "retention": {"max_messages": null, "max_age_ms": null},
+                "gate": null,
+                "subscription": null,
+                "topic": "Anything",
+                "description": "Talk here",
+                "tags": [],
+                "receivers": [],
+                "chain_head": null,
+                "created_at": 3,
+            })
+        );
+    }
+
+    fn private_channel(contract: &mut MetanearChat, channel_id: &str, members: &[AccountId]) {
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"SetChannelKind": {{"channel_id": "{}", "kind": "Private"}}}}"#, channel_id),
+        );
+        for account_id in members {
+            contract.post_message(
+                "chat".to_string(),
+                format!(r#"{{"AddMember": {{"channel_id": "{}", "account_id": "{}"}}}}"#, channel_id, account_id),
+            );
+        }
+    }
+
+    #[test]
+    fn test_private_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("secret", "psst"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "secret", "from_index": 0, "limit": 10, "reader_id": "bob.near"}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["body"]["Text"]["text"], "psst");
+    }
+
+    #[test]
+    #[should_panic(expected = "No access to the private channel")]
+    fn test_private_channel_post_by_non_member() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("secret", "hi"));
+    }
+
+    #[test]
+    #[should_panic(expected = "No access to the private channel")]
+    fn test_private_channel_read_without_reader() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+        contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "secret", "from_index": 0, "limit": 10}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "No access to the private channel")]
+    fn test_private_channel_moderation_log_without_reader() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+        contract.get(
+            "chat".to_string(),
+            r#"{"ModerationLog": {"channel_id": "secret", "from_index": 0, "limit": 10}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_private_channel_views_with_reader() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+        let requests = [
+            r#"{"ModerationLog": {"channel_id": "secret", "from_index": 0, "limit": 10, "reader_id": "bob.near"}}"#,
+            r#"{"HistoryCommitments": {"channel_id": "secret", "from_index": 0, "limit": 10, "reader_id": "bob.near"}}"#,
+            r#"{"ModerationQueue": {"channel_id": "secret", "from_index": 0, "limit": 10, "reader_id": "bob.near"}}"#,
+            r#"{"Bans": {"channel_id": "secret", "from_index": 0, "limit": 10, "reader_id": "bob.near"}}"#,
+            r#"{"ChannelStatus": {"channel_id": "secret", "reader_id": "bob.near"}}"#,
+            r#"{"TypingAccounts": {"channel_id": "secret", "reader_id": "bob.near"}}"#,
+            r#"{"ChannelActivity": {"channel_id": "secret", "from_day": 0, "to_day": 1, "reader_id": "bob.near"}}"#,
+            r#"{"TopPosters": {"channel_id": "secret", "limit": 10, "reader_id": "bob.near"}}"#,
+            r#"{"TopTipped": {"channel_id": "secret", "limit": 10, "reader_id": "bob.near"}}"#,
+        ];
+        for request in &requests {
+            assert!(contract.get("chat".to_string(), request.to_string()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_invites() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Invite": {"channel_id": "secret", "account_id": "carol.near"}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"PendingInvites": {"account_id": "carol.near"}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["invites"][0]["channel_id"], "secret");
+        assert_eq!(response["invites"][0]["inviter_id"], "bob.near");
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"AcceptInvite": {"channel_id": "secret"}}"#.to_string(),
+        );
+        contract.post_message("chat".to_string(), chat_message("secret", "thanks"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"PendingInvites": {"account_id": "carol.near"}}"#.to_string(),
+        ).unwrap();
+        assert_eq!(response, r#"{"invites":[]}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "No pending invite to the channel")]
+    fn test_accept_without_invite() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[bob()]);
+        private_channel(&mut contract, "other", &[bob()]);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Invite": {"channel_id": "other", "account_id": "carol.near"}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"AcceptInvite": {"channel_id": "secret"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_channel_owner() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("bobs", "my channel"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetChannelMeta": {"channel_id": "bobs", "topic": "Bob's", "description": ""}}"#.to_string(),
+        );
+        contract.add_moderator("bobs".to_string(), carol());
+
+        let channel = contract.get_channel("bobs".to_string());
+        assert_eq!(channel.owner_id, bob());
+        assert_eq!(channel.topic, "Bob's");
+        assert!(channel.is_moderator(&carol()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the channel owner can do this")]
+    fn test_archive_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("bobs", "my channel"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetArchived": {"channel_id": "bobs", "archived": true}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_join_leave() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), bootstrap_channels(&["general", "random", "dev"]));
+        for channel_id in &["general", "random", "dev"] {
+            contract.post_message(
+                "chat".to_string(),
+                format!(r#"{{"JoinChannel": {{"channel_id": "{}"}}}}"#, channel_id),
+            );
+        }
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"LeaveChannel": {"channel_id": "random"}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"AccountChannels": {"account_id": "bob.near", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_channels"], 2);
+        let mut channel_ids: Vec<_> = response["channels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|channel| channel["channel_id"].as_str().unwrap().to_string())
+            .collect();
+        channel_ids.sort();
+        assert_eq!(channel_ids, vec!["dev".to_string(), "general".to_string()]);
+        assert_eq!(response["channels"][0]["num_members"], 1);
+        assert_eq!(contract.get_channel("random".to_string()).members.len(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Private channels require an invite")]
+    fn test_join_private_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        private_channel(&mut contract, "secret", &[]);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"JoinChannel": {"channel_id": "secret"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Channel doesn't exist")]
+    fn test_join_missing_channel() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"JoinChannel": {"channel_id": "genral"}}"#.to_string());
+    }
+
+    #[test]
+    fn test_channel_members() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), bootstrap_channels(&["general"]));
+        for (account_id, time) in &[(bob(), 1_000_000), (carol(), 2_000_000)] {
+            set_predecessor(&mut context, account_id.clone());
+            context.block_timestamp = *time;
+            testing_env!(context.clone());
+            contract.post_message(
+                "chat".to_string(),
+                r#"{"JoinChannel": {"channel_id": "general"}}"#.to_string(),
+            );
+        }
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMembers": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "num_members": 2,
+                "members": [
+                    {"account_id": "bob.near", "joined_at": 1},
+                    {"account_id": "carol.near", "joined_at": 2},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_direct_messages() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        let result = contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"DirectMessage": {{"recipient_id": "carol.near", "body": {}}}}}"#, text_body("hi")),
+        );
+        assert_eq!(result.channel_id, direct_channel_id(&carol(), &bob()));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let reply = contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"DirectMessage": {{"recipient_id": "bob.near", "body": {}}}}}"#, text_body("hey")),
+        );
+        assert_eq!(reply.channel_id, result.channel_id);
+        assert_eq!(reply.message_index, 1);
+
+        let channel = contract.get_channel(result.channel_id);
+        assert!(channel.kind == ChannelKind::Direct);
+        assert!(channel.can_access(&bob()));
+        assert!(channel.can_access(&carol()));
+        assert!(!channel.can_access(&"dave.near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "The channel ID is reserved for direct messages")]
+    fn test_direct_channel_squatting() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        let channel_id = direct_channel_id(&bob(), &carol());
+        contract.post_message("chat".to_string(), chat_message(&channel_id, "mine"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The channel ID is reserved for direct messages")]
+    fn test_join_direct_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        let result = contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"DirectMessage": {{"recipient_id": "carol.near", "body": {}}}}}"#, text_body("hi")),
+        );
+
+        set_predecessor(&mut context, "dave.near".to_string());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"JoinChannel": {{"channel_id": "{}"}}}}"#, result.channel_id),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The channel ID is reserved for direct messages")]
+    fn test_bootstrap_direct_channel() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let channel_id = direct_channel_id(&bob(), &carol());
+        MetanearChat::new(alice(), bootstrap_channels(&[&channel_id]));
+    }
+
+    #[test]
+    fn test_group() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        let result = contract.post_message(
+            "chat".to_string(),
+            r#"{"CreateGroup": {"members": ["carol.near"], "title": "Weekend"}}"#.to_string(),
+        );
+        assert_eq!(result.channel_id, "group-0");
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"AddMember": {"channel_id": "group-0", "account_id": "dave.near"}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("group-0", "hi all"));
+
+        let channel = contract.get_channel("group-0".to_string());
+        assert_eq!(channel.owner_id, bob());
+        assert_eq!(channel.topic, "Weekend");
+        assert_eq!(channel.members.len(), 3);
+        assert!(!channel.can_access(&"eve.near".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "The channel ID is reserved for group conversations")]
+    fn test_join_group_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreateGroup": {"members": ["carol.near"], "title": "Weekend"}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, "dave.near".to_string());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), r#"{"JoinChannel": {"channel_id": "group-0"}}"#.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the channel owner can do this")]
+    fn test_group_add_member_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"CreateGroup": {"members": ["carol.near"], "title": "Weekend"}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"AddMember": {"channel_id": "group-0", "account_id": "dave.near"}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_announcement_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetAnnouncement": {"channel_id": "news", "announcement": true}}"#.to_string(),
+        );
+        contract.add_moderator("news".to_string(), bob());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("news", "release is out"));
+
+        assert_eq!(contract.get_channel("news".to_string()).messages.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner and moderators can post to the announcement channel")]
+    fn test_post_to_announcement_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetAnnouncement": {"channel_id": "news", "announcement": true}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("news", "nice"));
+    }
+
+    #[test]
+    fn test_slow_mode() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetSlowMode": {"channel_id": "general", "slow_mode_ms": 1000}}"#.to_string(),
+        );
+        contract.post_message("chat".to_string(), chat_message("general", "owner isn't limited"));
+        contract.post_message("chat".to_string(), chat_message("general", "at all"));
+
+        set_predecessor(&mut context, bob());
+        context.block_timestamp = 5_000_000_000;
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        context.block_timestamp = 6_000_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "a second later"));
+        assert_eq!(contract.get_channel("general".to_string()).messages.len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Slow mode is enabled, wait before posting again")]
+    fn test_slow_mode_post_too_soon() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetSlowMode": {"channel_id": "general", "slow_mode_ms": 1000}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        context.block_timestamp = 5_000_000_000;
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        context.block_timestamp = 5_500_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hello again"));
+    }
+
+    #[test]
+    fn test_post_rate_limit() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_posts_per_window: 2,
+            ..Config::default()
+        });
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("random", "hello"));
+
+        context.block_timestamp = 60_000_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hello again"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many messages, wait before posting again")]
+    fn test_post_rate_limit_exceeded() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_posts_per_window: 2,
+            ..Config::default()
+        });
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("random", "hello"));
+        contract.post_message("chat".to_string(), chat_message("general", "hello again"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough storage balance to cover the storage of the message")]
+    fn test_create_channel_without_deposit() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        context.attached_deposit = 0;
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+    }
+
+    #[test]
+    fn test_post_paid_from_storage_balance() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.storage_deposit(None, None);
+
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+        let balance: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+        assert!(balance > 0 && balance < 10u128.pow(24));
+
+        context.attached_deposit = 1;
+        testing_env!(context);
+        let storage_balance = contract.storage_withdraw(None);
+        assert_eq!(storage_balance.available, "0");
+    }
+
+    #[test]
+    fn test_message_deposit_released() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            message_deposit: "1000".to_string(),
+            message_deposit_cooldown_ms: 1000,
+            ..Config::default()
+        });
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+        let balance: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+
+        context.block_timestamp = 1_000_000_000;
+        testing_env!(context);
+        contract.release_message_deposit("general".to_string(), 0);
+        let released: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+        assert_eq!(released, balance + 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "No deposit is held for the message")]
+    fn test_message_deposit_forfeited() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            message_deposit: "1000".to_string(),
+            message_deposit_cooldown_ms: 1000,
+            ..Config::default()
+        });
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+
+        set_predecessor(&mut context, alice());
+        context.block_timestamp = 1_000_000_000;
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"ModDelete": {"channel_id": "general", "message_index": 1, "reason": "spam"}}"#.to_string(),
+        );
+        assert_eq!(contract.get_treasury().balance, "1000");
+        contract.release_message_deposit("general".to_string(), 1);
+    }
+
+    #[test]
+    fn test_delete_message_refunds_storage() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("general", &"a".repeat(1000)));
+        let balance: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"DeleteMessage": {"channel_id": "general", "message_index": 1}}"#.to_string(),
+        );
+        let refunded: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+        assert!(refunded >= balance + 1000 * STORAGE_PRICE_PER_BYTE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough storage balance to cover the storage of the message")]
+    fn test_post_without_storage_balance() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        set_predecessor(&mut context, bob());
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+    }
+
+    #[test]
+    fn test_trending_channels() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("old", "hello"));
+        contract.post_message("chat".to_string(), chat_message("old", "anyone?"));
+        contract.post_message("chat".to_string(), chat_message("old", "..."));
+
+        context.block_timestamp = 10_000_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("quiet", "hi"));
+        contract.post_message("chat".to_string(), chat_message("busy", "hi"));
+        contract.post_message("chat".to_string(), chat_message("busy", "hey"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"TrendingChannels": {"limit": 10, "window_ms": 5000}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["channels"],
+            serde_json::json!([
+                {"channel_id": "busy", "num_recent_messages": 2, "last_message_time": 10000},
+                {"channel_id": "quiet", "num_recent_messages": 1, "last_message_time": 10000},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_active_channels() {
+        let mut analytics = Analytics::default();
+        for index in 0..=NUM_ACTIVE_CHANNELS {
+            analytics.record_active_channel(&format!("channel{}", index));
+        }
+        analytics.record_active_channel(&"channel50".to_string());
+
+        assert_eq!(analytics.active_channel_ids.len(), NUM_ACTIVE_CHANNELS);
+        assert_eq!(analytics.active_channel_ids[0], "channel50");
+        assert_eq!(analytics.active_channel_ids[1], format!("channel{}", NUM_ACTIVE_CHANNELS));
+        assert!(!analytics.active_channel_ids.contains(&"channel0".to_string()));
+    }
+
+    #[test]
+    fn test_channels_by_tag() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetTags": {"channel_id": "rust", "tags": ["dev", "rust"]}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetTags": {"channel_id": "memes", "tags": ["dev", "memes"]}}"#.to_string(),
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetTags": {"channel_id": "memes", "tags": ["memes"]}}"#.to_string(),
+        );
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelsByTag": {"tag": "dev", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_channels"], 1);
+        assert_eq!(response["channels"][0]["channel_id"], "rust");
+        assert_eq!(contract.get_channel("memes".to_string()).tags, vec!["memes".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many tags")]
+    fn test_too_many_tags() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetTags": {"channel_id": "rust", "tags": ["a", "b", "c", "d", "e", "f"]}}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_retention_max_messages() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetRetention": {"channel_id": "general", "retention": {"max_messages": 2}}}"#.to_string(),
+        );
+        for text in &["one", "two", "three", "four"] {
+            contract.post_message("chat".to_string(), chat_message("general", text));
+        }
+
+        let channel = contract.get_channel("general".to_string());
+        assert_eq!(channel.num_pruned_messages, 2);
+        assert!(channel.message(1).unwrap().deleted);
+        assert!(!channel.message(2).unwrap().deleted);
+    }
+
+    #[test]
+    fn test_prune_channel_by_age() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "old"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetRetention": {"channel_id": "general", "retention": {"max_age_ms": 1000}}}"#.to_string(),
+        );
+
+        context.block_timestamp = 5_000_000_000;
+        testing_env!(context);
+        assert_eq!(contract.prune_channel("general".to_string(), 10), 1);
+        assert_eq!(contract.get_channel("general".to_string()).num_pruned_messages, 1);
+    }
+
+    #[test]
+    fn test_prune_refunds_storage_once() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetRetention": {"channel_id": "general", "retention": {"max_messages": 3}}}"#.to_string(),
+        );
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", &"a".repeat(1000)));
+        let bob_balance: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+        let first_balance: Balance = contract.storage_balance_of(carol()).unwrap().available.parse().unwrap();
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+        let balance: Balance = contract.storage_balance_of(carol()).unwrap().available.parse().unwrap();
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+
+        assert!(contract.get_channel("general".to_string()).message(0).unwrap().deleted);
+        let new_balance: Balance = contract.storage_balance_of(carol()).unwrap().available.parse().unwrap();
+        assert_eq!(balance - new_balance, first_balance - balance);
+        let refunded: Balance = contract.storage_balance_of(bob()).unwrap().available.parse().unwrap();
+        assert!(refunded >= bob_balance + 1000 * STORAGE_PRICE_PER_BYTE);
+    }
+
+    #[test]
+    fn test_prune_bridged_message_refunds_peer() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_trusted_peer("peer.near".to_string());
+
+        set_predecessor(&mut context, "peer.near".to_string());
+        testing_env!(context);
+        let body: MessageBody = serde_json::from_str(&text_body("hello")).unwrap();
+        contract.bridge_message("general".to_string(), carol(), body, vec![], "lobby".to_string(), 7);
+
+        let mut channel = contract.get_channel("general".to_string());
+        channel.retention.max_messages = Some(0);
+        let pruned = channel.prune(0, 10);
+        assert_eq!(pruned[0].account_id, "peer.near");
+    }
+
+    #[test]
+    fn test_prune_migrated_message_refunds_nobody() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut old_channel = ChannelV1 {
+            channel_id: "general".to_string(),
+            messages: Vector::new(messages_key_from_hash(env::sha256(b"general"))),
+        };
+        old_channel.messages.push(&MessageV1 { time: 3, sender_id: bob(), text: "hello".to_string() });
+        let mut old_state = MetanearChatV1 { channels: Map::new(b"c".to_vec()), total_num_messages: 1 };
+        old_state.channels.insert(&env::sha256(b"general"), &old_channel);
+        env::state_write(&old_state);
+        let mut contract = MetanearChat::migrate(alice());
+        contract.migrate_channels(10);
+
+        let mut channel = contract.get_channel("general".to_string());
+        channel.retention.max_messages = Some(0);
+        let pruned = channel.prune(0, 10);
+        assert_eq!(pruned[0].account_id, env::current_account_id());
+    }
+
+    #[test]
+    fn test_history_commitments() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.post_message("chat".to_string(), chat_message("general", "two"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetRetention": {"channel_id": "general", "retention": {"max_age_ms": 1000}}}"#.to_string(),
+        );
+        let channel = contract.get_channel("general".to_string());
+        let leaves: Vec<Vec<u8>> =
+            (0..2).map(|index| env::sha256(&channel.message(index).unwrap().try_to_vec().unwrap())).collect();
+        let root = hex_encode(&env::sha256(&leaves.concat()));
+
+        context.block_timestamp = 5_000_000_000;
+        testing_env!(context);
+        assert_eq!(contract.prune_channel("general".to_string(), 10), 2);
+        let request = r#"{"HistoryCommitments": {"channel_id": "general", "from_index": 0, "limit": 10}}"#;
+        assert_eq!(
+            contract.get("chat".to_string(), request.to_string()),
+            Some(format!(
+                r#"{{"num_commitments":1,"commitments":[{{"from_index":0,"to_index":2,"root":"{}","time":5000}}]}}"#,
+                root
+            ))
+        );
+    }
+
+    #[test]
+    fn test_message_chain_links() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.post_message("chat".to_string(), chat_message("general", "two"));
+
+        let channel = contract.get_channel("general".to_string());
+        let first_link = env::sha256(&channel.message(0).unwrap().try_to_vec().unwrap());
+        let second_message = channel.message(1).unwrap().try_to_vec().unwrap();
+        let second_link = env::sha256(&[first_link.clone(), second_message].concat());
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["chain_link"], hex_encode(&first_link));
+        assert_eq!(response["messages"][1]["chain_link"], hex_encode(&second_link));
+        let response = contract.get("chat".to_string(), r#"{"ChannelStatus": {"channel_id": "general"}}"#.to_string());
+        let response: serde_json::Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert_eq!(response["chain_head"], hex_encode(&second_link));
+    }
+
+    #[test]
+    fn test_channel_activity() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.post_message("chat".to_string(), chat_message("general", "two"));
+
+        context.block_timestamp = 2 * MS_PER_DAY * 1000000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "three"));
+
+        let request = r#"{"ChannelActivity": {"channel_id": "general", "from_day": 0, "to_day": 5}}"#;
+        assert_eq!(
+            contract.get("chat".to_string(), request.to_string()).unwrap(),
+            r#"{"days":[{"day":0,"num_messages":2},{"day":2,"num_messages":1}]}"#
+        );
+    }
+
+    #[test]
+    fn test_analytics() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.post_message("chat".to_string(), chat_message("random", "two"));
+        contract.post_message("chat".to_string(), chat_message("random", "three"));
+
+        context.block_timestamp = 2 * MS_PER_DAY * 1000000;
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "four"));
+
+        let response = contract.get("chat".to_string(), r#"{"Analytics": {}}"#.to_string()).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["top_channels"],
+            serde_json::json!([
+                {"channel_id": "random", "num_messages": 2},
+                {"channel_id": "general", "num_messages": 2},
+            ])
+        );
+        assert_eq!(response["num_senders"], 2);
+        assert_eq!(response["num_recent_messages"], 1);
+        assert!(response["storage_usage"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_analytics_top_channels_are_public() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "one"));
+        contract.post_message("chat".to_string(), chat_message("random", "two"));
+        private_channel(&mut contract, "random", &[bob()]);
+        private_channel(&mut contract, "secret", &[bob()]);
+        contract.post_message("chat".to_string(), chat_message("secret", "three"));
+
+        let response = contract.get("chat".to_string(), r#"{"Analytics": {}}"#.to_string()).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["top_channels"], serde_json::json!([{"channel_id": "general", "num_messages": 1}]));
+    }
+
+    #[test]
+    fn test_typed_views() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        assert_eq!(
+            serde_json::to_string(&contract.get_status()).unwrap(),
+            contract.get("chat".to_string(), r#"{"Status": {}}"#.to_string()).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&contract.get_channel_status("general".to_string())).unwrap(),
+            contract.get("chat".to_string(), r#"{"ChannelStatus": {"channel_id": "general"}}"#.to_string()).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&contract.get_channel_messages("general".to_string(), 0, 10)).unwrap(),
+            contract.get(
+                "chat".to_string(),
+                r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+            ).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_borsh_views() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        let status = base64::decode(&contract.get_status_borsh()).unwrap();
+        assert_eq!(status, contract.get_status().try_to_vec().unwrap());
+        let messages = base64::decode(&contract.get_channel_messages_borsh("general".to_string(), 0, 10)).unwrap();
+        // The number of messages is followed by the time of the first message.
+        assert_eq!(messages[..4], 1u32.to_le_bytes());
+        assert_eq!(messages[4..12], 0u64.to_le_bytes());
+        assert_eq!(
+            contract.get_channel_status_borsh("general".to_string()),
+            base64::encode(&contract.get_channel_status("general".to_string()).try_to_vec().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_batch_request() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        let request = r#"{"Batch": [{"Status": {}}, {"TopPosters": {"limit": 10}}]}"#;
+        assert_eq!(
+            contract.get("chat".to_string(), request.to_string()).unwrap(),
+            r#"[{"num_channels":1,"total_num_messages":1},{"accounts":[{"account_id":"alice.near","num_messages":1}]}]"#
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Batches can't be nested")]
+    fn test_nested_batch_request() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let contract = MetanearChat::new(alice(), None);
+        contract.get("chat".to_string(), r#"{"Batch": [{"Batch": []}]}"#.to_string());
+    }
+
+    #[test]
+    fn test_channel_messages_by_time() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        for (time, text) in &[(1000, "one"), (2000, "two"), (3000, "three"), (4000, "four")] {
+            context.block_timestamp = time * 1000000;
+            testing_env!(context.clone());
+            contract.post_message("chat".to_string(), chat_message("general", text));
+        }
+        let request = r#"{"ChannelMessagesByTime": {"channel_id": "general", "from_time": 1500, "to_time": 4000, "limit": 10}}"#;
+        let response = contract.get("chat".to_string(), request.to_string()).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        let indices: Vec<u64> = response["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(indices, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_versioned_message() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        let channel = contract.get_channel("general".to_string());
+        let raw_message = channel.messages.get_raw(0).unwrap();
+        assert_eq!(raw_message[0], 0);
+        assert_eq!(raw_message[1..], channel.message(0).unwrap().try_to_vec().unwrap()[..]);
+    }
+
+    #[test]
+    fn test_channel_ids() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.post_message("chat".to_string(), chat_message("random", "hi"));
+        contract.post_message("chat".to_string(), chat_message("general", "hello again"));
+
+        assert_eq!(contract.channel_ids.to_vec(), vec!["general".to_string(), "random".to_string()]);
+        assert_eq!(contract.read_channel(&"general".to_string()).unwrap().messages.len(), 2);
+    }
+
+    #[test]
+    fn test_versioned_channel() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        let stored = VersionedChannel::try_from_slice(&env::storage_read(&channel_key(&"general".to_string())).unwrap()).unwrap();
+        let bytes = stored.try_to_vec().unwrap();
+        let VersionedChannel::V1(channel) = stored;
+        assert_eq!(bytes, VersionedChannel::current_bytes(&channel));
+        assert_eq!(channel.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_from_v1() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut old_channel = ChannelV1 {
+            channel_id: "general".to_string(),
+            messages: Vector::new(messages_key_from_hash(env::sha256(b"general"))),
+        };
+        old_channel.messages.push(&MessageV1 { time: 3, sender_id: bob(), text: "hello".to_string() });
+        let mut old_state = MetanearChatV1 { channels: Map::new(b"c".to_vec()), total_num_messages: 1 };
+        old_state.channels.insert(&env::sha256(b"general"), &old_channel);
+        env::state_write(&old_state);
+
+        let mut contract = MetanearChat::migrate(alice());
+        assert_eq!(contract.total_num_messages, 1);
+        assert_eq!(contract.migrate_channels(10), 0);
+        let channel = contract.get_channel("general".to_string());
+        assert_eq!(channel.created_at, 3);
+        match channel.message(0).unwrap().body {
+            MessageBody::Text { text } => assert_eq!(text, "hello"),
+            _ => panic!("Expected a text message"),
+        }
+    }
+
+    #[test]
+    fn test_migrate_messages_in_batches() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut old_channel = ChannelV1 {
+            channel_id: "general".to_string(),
+            messages: Vector::new(messages_key_from_hash(env::sha256(b"general"))),
+        };
+        for (time, text) in &[(3, "one"), (4, "two"), (5, "three")] {
+            old_channel.messages.push(&MessageV1 { time: *time, sender_id: bob(), text: text.to_string() });
+        }
+        let mut old_state = MetanearChatV1 { channels: Map::new(b"c".to_vec()), total_num_messages: 3 };
+        old_state.channels.insert(&env::sha256(b"general"), &old_channel);
+        env::state_write(&old_state);
 
-#[derive(Deserialize)]
-pub enum GetRequest {
-    Status {},
-    ChannelStatus {
-        channel_id: ChannelId
-    },
-    ChannelMessages {
-        channel_id: ChannelId,
-        from_index: u64,
-        limit: u64,
+        let mut contract = MetanearChat::migrate(alice());
+        assert_eq!(contract.migrate_channels(2), 1);
+        assert_eq!(contract.read_channel(&"general".to_string()).unwrap().messages.len(), 2);
+        assert_eq!(contract.migrate_channels(2), 0);
+        let channel = contract.get_channel("general".to_string());
+        assert_eq!(channel.created_at, 3);
+        assert_eq!(channel.messages.len(), 3);
+        match channel.message(2).unwrap().body {
+            MessageBody::Text { text } => assert_eq!(text, "three"),
+            _ => panic!("Expected a text message"),
+        }
     }
-}
 
-#[derive(Serialize)]
-pub struct StatusResponse {
-    num_channels: u64,
-    total_num_messages: u64,
-}
+    #[test]
+    #[should_panic(expected = "Self calls only")]
+    fn test_migrate_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.predecessor_account_id = bob();
+        testing_env!(context);
+        MetanearChat::migrate(alice());
+    }
 
-#[derive(Serialize)]
-pub struct ChannelStatusResponse {
-    num_messages: u64,
-}
+    #[test]
+    fn test_token_gated_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetGate": {"channel_id": "holders", "gate": {"FungibleToken": {"token_id": "token.near", "min_balance": "100"}}}}"#.to_string(),
+        );
 
-#[derive(Serialize)]
-pub struct ChannelMessagesResponse {
-    messages: Vec<Message>,
-}
+        testing_env_with_promise_result(context.clone(), PromiseResult::Successful(b"\"99\"".to_vec()));
+        assert!(!contract.on_gate_check("holders".to_string(), bob()));
+        testing_env_with_promise_result(context.clone(), PromiseResult::Successful(b"\"100\"".to_vec()));
+        assert!(contract.on_gate_check("holders".to_string(), carol()));
 
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("holders", "gm"));
+        let channel = contract.get_channel("holders".to_string());
+        assert!(!channel.is_member(&bob()));
+        assert_eq!(channel.messages.len(), 1);
+    }
 
-#[derive(Deserialize)]
-pub enum IncomingMessage {
-    ChatMessage {
-        channel_id: ChannelId,
-        text: String,
+    #[test]
+    #[should_panic(expected = "Only members can post to the gated channel")]
+    fn test_post_to_gated_channel_without_membership() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetGate": {"channel_id": "holders", "gate": {"FungibleToken": {"token_id": "token.near", "min_balance": "100"}}}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("holders", "gm"));
     }
-}
 
-fn verify_app_id(app_id: &AppId) {
-    if app_id.len() < 2 || app_id.len() > 64 {
-        env::panic(b"App ID length should be between 2 and 64 characters");
+    #[test]
+    fn test_nft_gated_channel() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetGate": {"channel_id": "club", "gate": {"NonFungibleToken": {"contract_id": "nft.near"}}}}"#.to_string(),
+        );
+
+        testing_env_with_promise_result(context.clone(), PromiseResult::Successful(b"[]".to_vec()));
+        assert!(!contract.on_gate_check("club".to_string(), bob()));
+        testing_env_with_promise_result(
+            context,
+            PromiseResult::Successful(br#"[{"token_id": "1", "owner_id": "carol.near"}]"#.to_vec()),
+        );
+        assert!(contract.on_gate_check("club".to_string(), carol()));
+        assert!(contract.get_channel("club".to_string()).is_member(&carol()));
     }
-    for c in app_id.bytes() {
-        match c {
-            b'a'..=b'z' => (),
-            b'0'..=b'9' => (),
-            b'-' | b'_' | b'.' => (),
-            _ => env::panic(
-                b"Unsupported character in the app ID. Only allowed to use `-.|` and 0-9 a-z",
-            ),
-        }
+
+    #[test]
+    fn test_ban_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.add_moderator("general".to_string(), carol());
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"BanAccount": {"channel_id": "general", "account_id": "bob.near", "reason": "spam"}}"#.to_string(),
+        );
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"Bans": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "num_bans": 1,
+                "bans": [{"account_id": "bob.near", "moderator_id": "carol.near", "reason": "spam", "time": 0}],
+            })
+        );
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"UnbanAccount": {"channel_id": "general", "account_id": "bob.near"}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "sorry"));
+        assert_eq!(contract.get_channel("general".to_string()).messages.len(), 1);
     }
-}
 
-fn verify_channel_id(channel_id: &ChannelId) {
-    if channel_id.len() < 1 || channel_id.len() > 128 {
-        env::panic(b"Channel length should be between 1 and 128 characters");
+    #[test]
+    #[should_panic(expected = "The account is banned from the channel")]
+    fn test_post_by_banned_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"BanAccount": {"channel_id": "general", "account_id": "bob.near", "reason": "spam"}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "buy now"));
     }
-    for c in channel_id.bytes() {
-        match c {
-            b'a'..=b'z' => (),
-            b'0'..=b'9' => (),
-            b'-' | b'_' | b'.' => (),
-            _ => env::panic(
-                b"Unsupported character in the channel. Only allowed to use `-.|` and 0-9 a-z",
-            ),
+
+    #[test]
+    fn test_mute_expires() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"MuteAccount": {"channel_id": "general", "account_id": "bob.near", "duration_ms": 1000}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        context.block_timestamp = 1_000_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "I'm back"));
+        let channel = contract.get_channel("general".to_string());
+        assert_eq!(channel.messages.len(), 1);
+        assert!(channel.mutes.get(&bob()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is muted in the channel")]
+    fn test_post_by_muted_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"MuteAccount": {"channel_id": "general", "account_id": "bob.near", "duration_ms": 1000}}"#.to_string(),
+        );
+
+        set_predecessor(&mut context, bob());
+        context.block_timestamp = 999_000_000;
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hello?"));
+    }
+
+    #[test]
+    fn test_bootstrap_channels() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let bootstrap_channels: Vec<BootstrapChannel> =
+            serde_json::from_str(r#"[{"channel_id": "general", "topic": "Start here"}, {"channel_id": "random"}]"#)
+                .unwrap();
+        let contract = MetanearChat::new(alice(), Some(bootstrap_channels));
+
+        assert_eq!(contract.channel_ids.len(), 2);
+        let channel = contract.get_channel("general".to_string());
+        assert_eq!(channel.topic, "Start here");
+        assert_eq!(channel.owner_id, alice());
+    }
+
+    #[test]
+    fn test_export_channel() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.block_timestamp = 7_000_000;
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        for text in &["one", "two", "three"] {
+            contract.post_message("chat".to_string(), chat_message("general", text));
         }
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ExportChannel": {"channel_id": "general", "from_index": 1, "limit": 1}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_messages"], 3);
+        assert_eq!(response["next_index"], 2);
+        assert_eq!(response["messages"][0]["index"], 1);
+        assert_eq!(response["messages"][0]["time"], 7);
+        assert_eq!(response["messages"][0]["body"], serde_json::json!({"Text": {"text": "two"}}));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ExportChannel": {"channel_id": "general", "from_index": 2, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["next_index"], serde_json::Value::Null);
     }
-}
 
-fn app_key(app_id: &AppId, key: &Key) -> Vec<u8> {
-    let app_id_hash = env::sha256(app_id.as_bytes());
-    let key_hash = env::sha256(key.as_bytes());
-    let mut res = Vec::with_capacity(app_id_hash.len() + key_hash.len() + 1);
-    res.push(b'a');
-    res.extend(app_id_hash);
-    res.extend(key_hash);
-    res
-}
+    #[test]
+    fn test_export_channel_redacts_hidden_messages() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReportThreshold": {"channel_id": "general", "report_threshold": 1}}"#.to_string(),
+        );
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"Report": {"channel_id": "general", "message_index": 0, "reason": "spam"}}"#.to_string(),
+        );
 
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ExportChannel": {"channel_id": "general", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["redacted"], true);
+        assert_eq!(response["messages"][0]["body"], serde_json::json!({"Text": {"text": DELETED_MESSAGE_TEXT}}));
+    }
 
-fn messages_key_from_hash(channel_hash: ChannelHash) -> Vec<u8> {
-    let mut res = Vec::with_capacity(channel_hash.len() + 1);
-    res.push(b'm');
-    res.extend_from_slice(&channel_hash);
-    res
-}
+    #[test]
+    fn test_profiles() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetProfile": {"display_name": "Bob", "avatar_url": "https://example.com/bob.png"}}"#.to_string(),
+        );
 
-impl Default for MetanearChat {
-    fn default() -> Self {
-        env::panic(b"Not initialized yet.");
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"Profiles": {"account_ids": ["bob.near", "carol.near"]}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "profiles": {
+                    "bob.near": {"display_name": "Bob", "avatar_url": "https://example.com/bob.png", "bio": ""},
+                },
+            })
+        );
     }
-}
 
-fn assert_self() {
-    assert_eq!(env::current_account_id(), env::predecessor_account_id(), "Self calls only");
-}
+    #[test]
+    fn test_custom_emoji() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        for name in &["partyparrot", "wave"] {
+            contract.post_message(
+                "chat".to_string(),
+                format!(r#"{{"SetCustomEmoji": {{"channel_id": "general", "name": "{}", "cid": "Qmaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}}}}"#, name),
+            );
+        }
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetCustomEmoji": {"channel_id": "general", "name": "wave", "cid": null}}"#.to_string(),
+        );
 
-#[near_bindgen]
-impl MetanearChat {
-    #[init]
-    pub fn new() -> Self {
-        assert!(env::state_read::<MetanearChat>().is_none(), "The contract is already initialized");
-        Self {
-            channels: Map::new(b"c".to_vec()),
-            total_num_messages: 0,
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"CustomEmoji": {"channel_id": "general"}}"#.to_string(),
+        ).unwrap();
+        assert_eq!(response, r#"{"custom_emoji":{"partyparrot":"Qmaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many custom emoji in the channel")]
+    fn test_too_many_custom_emoji() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.update_config(Config {
+            max_custom_emoji: 1,
+            ..Config::default()
+        });
+        for name in &["partyparrot", "wave"] {
+            contract.post_message(
+                "chat".to_string(),
+                format!(r#"{{"SetCustomEmoji": {{"channel_id": "general", "name": "{}", "cid": "Qmaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}}}}"#, name),
+            );
         }
     }
 
-    pub fn master_set(&mut self, app_id: AppId, key: Key, value: Value) {
-        assert_self();
-        env::storage_write(&app_key(&app_id, &key), &value.as_bytes());
+    #[test]
+    fn test_set_receivers() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReceivers": {"channel_id": "general", "receivers": ["bot.near", "bridge.near"]}}"#.to_string(),
+        );
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelStatus": {"channel_id": "general"}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["receivers"], serde_json::json!(["bot.near", "bridge.near"]));
     }
 
-    pub fn master_remove(&mut self, app_id: AppId, key: Key) {
-        assert_self();
-        env::storage_remove(&app_key(&app_id, &key));
+    #[test]
+    fn test_notifications_in_full_batch() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.prepaid_gas = 300_000_000_000_000;
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"SetMentionReceiver": {"receiver_id": "push.near"}}"#.to_string());
+
+        set_predecessor(&mut context, alice());
+        testing_env!(context.clone());
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReceivers": {"channel_id": "general", "receivers": ["a.near", "b.near", "c.near"]}}"#.to_string(),
+        );
+
+        testing_env!(context);
+        let messages = (0..20).map(|index| chat_message("general", &format!("hi @bob.near {}", index))).collect();
+        let results = contract.post_messages("chat".to_string(), messages);
+        assert_eq!(results.len(), 20);
+        assert!(env::used_gas() <= env::prepaid_gas());
     }
 
-    pub fn get(&self, app_id: AppId, key: Key) -> Option<Value> {
-        verify_app_id(&app_id);
-        if app_id.as_bytes() == CHAT_APP_ID {
-            let request: GetRequest = serde_json::from_str(&key).expect("Can't parse key request");
-            match request {
-                GetRequest::Status {} => {
-                    Some(serde_json::to_string(&StatusResponse {
-                        num_channels: self.channels.len(),
-                        total_num_messages: self.total_num_messages,
-                    }).unwrap())
-                },
-                GetRequest::ChannelStatus { channel_id } => {
-                    let channel = self.get_channel(channel_id);
-                    Some(serde_json::to_string(&ChannelStatusResponse {
-                        num_messages: channel.messages.len(),
-                    }).unwrap())
-                },
-                GetRequest::ChannelMessages { channel_id, from_index, limit } => {
-                    let channel = self.get_channel(channel_id);
-                    let mut messages = Vec::new();
-                    let mut index = from_index;
-                    while (messages.len() as u64) < limit && index < channel.messages.len() {
-                        messages.push(channel.messages.get(index).unwrap());
-                        index += 1;
-                    }
-                    Some(serde_json::to_string(&ChannelMessagesResponse {
-                        messages,
-                    }).unwrap())
-                },
-            }
-        } else {
-            env::storage_read(&app_key(&app_id, &key)).map(|bytes| String::from_utf8(bytes).unwrap())
+    #[test]
+    fn test_set_mention_receiver() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"SetMentionReceiver": {"receiver_id": "push.near"}}"#.to_string());
+        assert_eq!(contract.mention_receivers.get(&bob()), Some("push.near".to_string()));
+
+        set_predecessor(&mut context, alice());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), chat_message("general", "hi @bob.near"));
+        assert_eq!(contract.mentions.get(&bob()).unwrap().len(), 1);
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), r#"{"SetMentionReceiver": {"receiver_id": null}}"#.to_string());
+        assert!(contract.mention_receivers.get(&bob()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many receivers")]
+    fn test_set_too_many_receivers() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetReceivers": {"channel_id": "general", "receivers": ["a.near", "b.near", "c.near", "d.near"]}}"#
+                .to_string(),
+        );
+    }
+
+    #[test]
+    fn test_crosspost() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("news", "first"));
+        let result = contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"CrossPost": {{"channel_ids": ["general", "news"], "body": {}}}}}"#, text_body("hello")),
+        );
+        assert_eq!(result.channel_id, "general");
+        assert_eq!(result.message_index, 0);
+
+        for (channel_id, message_index) in &[("general", 0), ("news", 1)] {
+            let response = contract.get(
+                "chat".to_string(),
+                format!(
+                    r#"{{"MessagesByIndices": {{"channel_id": "{}", "indices": [{}]}}}}"#,
+                    channel_id, message_index
+                ),
+            ).unwrap();
+            let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(response["messages"][0]["crosspost_id"], 0);
+            assert_eq!(response["messages"][0]["body"]["Text"]["text"], "hello");
         }
     }
 
-    /// Called when receiving a message
-    pub fn post_message(&mut self, app_id: AppId, message: String) {
-        verify_app_id(&app_id);
-        assert_eq!(app_id.as_bytes(), CHAT_APP_ID, "I only support chat messages");
+    #[test]
+    #[should_panic(expected = "Duplicate channel")]
+    fn test_crosspost_to_duplicate_channels() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"CrossPost": {{"channel_ids": ["general", "general"], "body": {}}}}}"#, text_body("hello")),
+        );
+    }
 
-        let sender_id = env::predecessor_account_id();
+    #[test]
+    fn test_typing_accounts() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        let typing = r#"{"Typing": {"channel_id": "general"}}"#;
+        contract.post_message("chat".to_string(), typing.to_string());
 
-        let incoming_message: IncomingMessage = serde_json::from_str(&message).expect("Can't parse the message");
-        match incoming_message {
-            IncomingMessage::ChatMessage { channel_id, text } => {
-                let mut channel = self.get_channel(channel_id);
-                channel.add_message(sender_id, text);
-                self.save_channel(&channel);
-                self.total_num_messages += 1;
-            },
-        };
+        context.block_timestamp = 6000 * 1000000;
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.post_message("chat".to_string(), typing.to_string());
+        let request = r#"{"TypingAccounts": {"channel_id": "general"}}"#;
+        assert_eq!(contract.get("chat".to_string(), request.to_string()).unwrap(), r#"{"account_ids":["bob.near","carol.near"]}"#);
+
+        context.block_timestamp = 12000 * 1000000;
+        testing_env!(context);
+        assert_eq!(contract.get("chat".to_string(), request.to_string()).unwrap(), r#"{"account_ids":["carol.near"]}"#);
+        contract.post_message("chat".to_string(), typing.to_string());
+        assert_eq!(contract.typing.get(&"general".to_string()).unwrap().len(), 1);
     }
-}
 
-impl MetanearChat {
-    pub fn get_channel(&self, channel_id: ChannelId) -> Channel {
-        verify_channel_id(&channel_id);
-        let channel_hash = env::sha256(channel_id.as_bytes());
-        self.channels.get(&channel_hash).unwrap_or_else(|| Channel::new(channel_id))
+    #[test]
+    fn test_encryption_keys() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetEncryptionKey": {"public_key": "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="}}"#.to_string(),
+        );
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            r#"{"SetEncryptionKey": {"public_key": "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="}}"#.to_string(),
+        );
+        contract.post_message("chat".to_string(), r#"{"SetEncryptionKey": {"public_key": null}}"#.to_string());
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"EncryptionKeys": {"account_ids": ["bob.near", "carol.near"]}}"#.to_string(),
+        ).unwrap();
+        assert_eq!(response, r#"{"encryption_keys":{"bob.near":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="}}"#);
     }
 
-    pub fn save_channel(&mut self, channel: &Channel) {
-        let channel_hash = env::sha256(channel.channel_id.as_bytes());
-        self.channels.insert(&channel_hash, &channel);
+    #[test]
+    #[should_panic(expected = "Encryption key should be a base64-encoded 32-byte X25519 public key")]
+    fn test_set_invalid_encryption_key() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"SetEncryptionKey": {"public_key": "AQID"}}"#.to_string());
     }
-}
 
+    #[test]
+    fn test_post_signed_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        set_signing_key(&mut contract);
 
-impl Channel {
-    pub fn new(channel_id: ChannelId) -> Self {
-        Self {
-            messages: Vector::new(messages_key_from_hash(env::sha256(channel_id.as_bytes()))),
-            channel_id,
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let payload = r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "relayed", "nonce": 1}"#;
+        let (signature, public_key) = sign_payload(payload);
+        let result = contract.post_signed_message(payload.to_string(), signature, public_key);
+
+        let message = contract.get_channel("general".to_string()).message(result.message_index).unwrap();
+        assert_eq!(message.sender_id, bob());
+        match message.body {
+            MessageBody::Text { text } => assert_eq!(text, "relayed"),
+            _ => panic!("Expected a text message"),
         }
     }
 
-    pub fn add_message(&mut self, sender_id: AccountId, text: String) {
-        self.messages.push(&Message {
-            sender_id,
-            text,
-            time: env::block_timestamp() / 1000000,
-        });
+    #[test]
+    fn test_signing_nonce() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        set_signing_key(&mut contract);
+        let request = r#"{"SigningNonce": {"account_id": "bob.near"}}"#;
+        assert_eq!(contract.get("chat".to_string(), request.to_string()).unwrap(), r#"{"nonce":0}"#);
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let payload = r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "hi", "nonce": 5}"#;
+        let (signature, public_key) = sign_payload(payload);
+        contract.post_signed_message(payload.to_string(), signature, public_key);
+        assert_eq!(contract.get("chat".to_string(), request.to_string()).unwrap(), r#"{"nonce":5}"#);
     }
-}
 
+    #[test]
+    #[should_panic(expected = "The nonce should be greater than the last nonce of the sender")]
+    fn test_replay_signed_message() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        set_signing_key(&mut contract);
 
-#[cfg(not(target_arch = "wasm32"))]
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_bindgen::MockedBlockchain;
-    use near_bindgen::{testing_env, VMContext};
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let payload = r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "hi", "nonce": 1}"#;
+        let (signature, public_key) = sign_payload(payload);
+        contract.post_signed_message(payload.to_string(), signature.clone(), public_key.clone());
+        contract.post_signed_message(payload.to_string(), signature, public_key);
+    }
 
-    fn alice() -> String {
-        "alice.near".to_string()
+    #[test]
+    #[should_panic(expected = "The payload is signed for another contract")]
+    fn test_replay_signed_message_from_another_contract() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        set_signing_key(&mut contract);
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let payload = r#"{"contract_id": "chat.near", "sender_id": "bob.near", "channel_id": "general", "text": "hi", "nonce": 1}"#;
+        let (signature, public_key) = sign_payload(payload);
+        contract.post_signed_message(payload.to_string(), signature, public_key);
     }
 
-    fn bob() -> String {
-        "bob.near".to_string()
+    #[test]
+    #[should_panic(expected = "Invalid signature")]
+    fn test_post_signed_message_with_invalid_signature() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        set_signing_key(&mut contract);
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let (signature, public_key) = sign_payload(r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "hi", "nonce": 1}"#);
+        let payload = r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "forged", "nonce": 1}"#;
+        contract.post_signed_message(payload.to_string(), signature, public_key);
     }
 
-    fn carol() -> String {
-        "carol.near".to_string()
+    #[test]
+    #[should_panic(expected = "The public key is not the signing key of the sender")]
+    fn test_post_signed_message_with_other_key() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        set_signing_key(&mut contract);
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        let payload = r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "hi", "nonce": 1}"#;
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let signature = base64::encode(&other_key.sign(payload.as_bytes()).to_bytes()[..]);
+        let public_key = base64::encode(other_key.verifying_key().as_bytes());
+        contract.post_signed_message(payload.to_string(), signature, public_key);
     }
 
-    fn get_context(signer_account_pk: Vec<u8>) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: alice(),
-            signer_account_pk,
-            predecessor_account_id: alice(),
-            input: vec![],
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 0,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
+    #[test]
+    #[should_panic(expected = "The sender has no signing key")]
+    fn test_post_signed_message_without_signing_key() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        let payload = r#"{"contract_id": "alice.near", "sender_id": "bob.near", "channel_id": "general", "text": "hi", "nonce": 1}"#;
+        let (signature, public_key) = sign_payload(payload);
+        contract.post_signed_message(payload.to_string(), signature, public_key);
+    }
+
+    #[test]
+    #[should_panic(expected = "Signing key should be a base64-encoded 32-byte ed25519 public key")]
+    fn test_set_invalid_signing_key() {
+        testing_env!(get_context(vec![0, 1, 2]));
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"SetSigningKey": {"public_key": "AQID"}}"#.to_string());
+    }
+
+    #[test]
+    fn test_channel_messages_with_profiles() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"SetProfile": {"display_name": "Bob"}}"#.to_string());
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+        contract.post_message("chat".to_string(), chat_message("general", "anyone?"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hey"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10, "include_profiles": true}}"#
+                .to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            response["profiles"],
+            serde_json::json!({"bob.near": {"display_name": "Bob", "avatar_url": "", "bio": ""}})
+        );
+    }
+
+    #[test]
+    fn test_presence() {
+        let mut context = get_context(vec![0, 1, 2]);
+        context.block_timestamp = 5_000_000;
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"Heartbeat": {}}"#.to_string());
+
+        context.block_timestamp = 8_000_000;
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), chat_message("general", "hi"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"Presence": {"account_ids": ["bob.near", "carol.near", "dave.near"]}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response, serde_json::json!({"last_active": {"bob.near": 5, "carol.near": 8}}));
+    }
+
+    #[test]
+    fn test_block_redacts_messages() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "spam"));
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.post_message("chat".to_string(), r#"{"Block": {"account_id": "bob.near"}}"#.to_string());
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"ChannelMessages": {"channel_id": "general", "from_index": 0, "limit": 10, "viewer_id": "carol.near"}}"#
+                .to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["messages"][0]["redacted"], true);
+        assert_eq!(response["messages"][0]["body"], serde_json::json!({"Text": {"text": ""}}));
+        assert_eq!(response["messages"][1]["redacted"], serde_json::Value::Null);
+        assert_eq!(response["messages"][1]["body"], serde_json::json!({"Text": {"text": "hello"}}));
+    }
+
+    #[test]
+    #[should_panic(expected = "The recipient blocked the sender")]
+    fn test_direct_message_from_blocked_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), r#"{"Block": {"account_id": "bob.near"}}"#.to_string());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.post_message(
+            "chat".to_string(),
+            format!(r#"{{"DirectMessage": {{"recipient_id": "carol.near", "body": {}}}}}"#, text_body("hi")),
+        );
+    }
+
+    #[test]
+    fn test_set_owner() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.set_owner(bob());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.master_set("app".to_string(), "key".to_string(), "value".to_string());
+        assert_eq!(contract.get("app".to_string(), "key".to_string()), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_app_keys() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set("app".to_string(), "a".to_string(), "1".to_string());
+        contract.master_set("app".to_string(), "b".to_string(), "2".to_string());
+        contract.master_set("app".to_string(), "b".to_string(), "3".to_string());
+        contract.master_set("app".to_string(), "c".to_string(), "4".to_string());
+        contract.master_set("other".to_string(), "d".to_string(), "5".to_string());
+        contract.master_remove("app".to_string(), "a".to_string());
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"AppKeys": {"app_id": "app", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["num_keys"], 2);
+        let mut keys: Vec<String> = serde_json::from_value(response["keys"].clone()).unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["b".to_string(), "c".to_string()]);
+
+        let response = contract.get(
+            "chat".to_string(),
+            r#"{"AppKeys": {"app_id": "missing", "from_index": 0, "limit": 10}}"#.to_string(),
+        ).unwrap();
+        assert_eq!(response, r#"{"num_keys":0,"keys":[]}"#);
+    }
+
+    #[test]
+    fn test_master_set_batch() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set_batch("app".to_string(), vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("c".to_string(), "3".to_string()),
+        ]);
+        assert_eq!(contract.get("app".to_string(), "b".to_string()), Some("2".to_string()));
+
+        contract.master_remove_batch("app".to_string(), vec!["a".to_string(), "c".to_string(), "d".to_string()]);
+        assert_eq!(contract.get("app".to_string(), "a".to_string()), None);
+        assert_eq!(contract.get_by_prefix("app".to_string(), "".to_string(), 10), vec![
+            ("b".to_string(), "2".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_master_set_base64() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set_base64("app".to_string(), "blob".to_string(), "AP8BAg==".to_string());
+        assert_eq!(env::storage_read(&app_key(&"app".to_string(), &"blob".to_string())), Some(vec![0, 255, 1, 2]));
+        assert_eq!(contract.get_base64("app".to_string(), "blob".to_string()), Some("AP8BAg==".to_string()));
+
+        contract.master_set("app".to_string(), "text".to_string(), "hi".to_string());
+        assert_eq!(contract.get_base64("app".to_string(), "text".to_string()), Some("aGk=".to_string()));
+        assert_eq!(contract.get_base64("app".to_string(), "missing".to_string()), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The value is binary, use `get_base64`")]
+    fn test_get_binary_value() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set_base64("app".to_string(), "blob".to_string(), "AP8BAg==".to_string());
+        contract.get("app".to_string(), "blob".to_string());
+    }
+
+    #[test]
+    fn test_master_compare_and_set() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        assert!(contract.master_compare_and_set("app".to_string(), "k".to_string(), None, "1".to_string()));
+        assert!(!contract.master_compare_and_set("app".to_string(), "k".to_string(), None, "2".to_string()));
+        assert!(!contract.master_compare_and_set(
+            "app".to_string(), "k".to_string(), Some("0".to_string()), "2".to_string(),
+        ));
+        assert_eq!(contract.get("app".to_string(), "k".to_string()), Some("1".to_string()));
+        assert!(contract.master_compare_and_set(
+            "app".to_string(), "k".to_string(), Some("1".to_string()), "2".to_string(),
+        ));
+        assert_eq!(contract.get("app".to_string(), "k".to_string()), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_app_writers() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.set_app_owner("app".to_string(), bob());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.master_set("app".to_string(), "a".to_string(), "1".to_string());
+        contract.add_app_writer("app".to_string(), carol());
+        assert_eq!(contract.get_app_writers("app".to_string()), vec![carol()]);
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context.clone());
+        contract.master_set("app".to_string(), "b".to_string(), "2".to_string());
+        assert_eq!(contract.get("app".to_string(), "b".to_string()), Some("2".to_string()));
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        contract.set_app_owner("app".to_string(), carol());
+        assert_eq!(contract.get_app_owner("app".to_string()), Some(carol()));
+    }
+
+    #[test]
+    fn test_register_app() {
+        let mut context = get_context(vec![0, 1, 2]);
+        set_predecessor(&mut context, bob());
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        let metadata = AppMetadata {
+            description: "Notes".to_string(),
+            icon_url: "https://example.com/notes.png".to_string(),
+            schema_version: 1,
+        };
+        contract.register_app("notes".to_string(), metadata);
+        contract.master_set("notes".to_string(), "a".to_string(), "1".to_string());
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.register_app("todo".to_string(), AppMetadata::default());
+
+        let apps = contract.get_apps(0, 10);
+        assert_eq!(apps.len(), 2);
+        assert_eq!(apps[0].app_id, "notes");
+        assert_eq!(apps[0].owner_id, bob());
+        assert_eq!(apps[0].metadata.schema_version, 1);
+        assert_eq!(apps[0].num_keys, 1);
+        assert_eq!(contract.get_app("todo".to_string()).unwrap().owner_id, carol());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can do this")]
+    fn test_register_app_with_existing_keys() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set("notes".to_string(), "a".to_string(), "1".to_string());
+
+        set_predecessor(&mut context, bob());
+        testing_env!(context);
+        contract.register_app("notes".to_string(), AppMetadata::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the app owner or writers can do this")]
+    fn test_app_write_by_other_account() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.set_app_owner("app".to_string(), bob());
+
+        set_predecessor(&mut context, carol());
+        testing_env!(context);
+        contract.master_set("app".to_string(), "a".to_string(), "1".to_string());
+    }
+
+    #[test]
+    fn test_master_set_with_ttl() {
+        let mut context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set_with_ttl("app".to_string(), "session".to_string(), "abc".to_string(), 1000);
+        contract.master_set("app".to_string(), "name".to_string(), "alice".to_string());
+        assert_eq!(contract.get("app".to_string(), "session".to_string()), Some("abc".to_string()));
+
+        context.block_timestamp += 1000 * 1000000;
+        testing_env!(context);
+        assert_eq!(contract.get("app".to_string(), "session".to_string()), None);
+        assert_eq!(contract.get_by_prefix("app".to_string(), "".to_string(), 10).len(), 1);
+        assert!(contract.master_compare_and_set("app".to_string(), "other".to_string(), None, "1".to_string()));
+        assert_eq!(contract.app_value_expirations.len(), 0);
+        assert!(env::storage_read(&app_key(&"app".to_string(), &"session".to_string())).is_none());
+        assert_eq!(contract.get_by_prefix("app".to_string(), "".to_string(), 10).len(), 2);
+    }
+
+    #[test]
+    fn test_get_by_prefix() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.master_set("app".to_string(), "settings/theme".to_string(), "dark".to_string());
+        contract.master_set("app".to_string(), "cache/feed".to_string(), "[]".to_string());
+        contract.master_set("app".to_string(), "settings/lang".to_string(), "en".to_string());
+        contract.master_set("app".to_string(), "settings/sound".to_string(), "off".to_string());
+
+        assert_eq!(
+            contract.get_by_prefix("app".to_string(), "settings/".to_string(), 2),
+            vec![
+                ("settings/lang".to_string(), "en".to_string()),
+                ("settings/sound".to_string(), "off".to_string()),
+            ]
+        );
+        assert_eq!(contract.get_by_prefix("app".to_string(), "cache/".to_string(), 10).len(), 1);
+        assert!(contract.get_by_prefix("other".to_string(), "".to_string(), 10).is_empty());
+    }
+
+    #[test]
+    fn test_get_by_prefix_scan_limit() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context.clone());
+        let mut contract = MetanearChat::new(alice(), None);
+        for index in 0..MAX_PREFIX_SCAN_KEYS {
+            // Every call starts with fresh gas.
+            testing_env!(context.clone());
+            contract.master_set("app".to_string(), format!("cache/{}", index), "[]".to_string());
         }
+        contract.master_set("app".to_string(), "settings/theme".to_string(), "dark".to_string());
+
+        assert!(contract.get_by_prefix("app".to_string(), "settings/".to_string(), 10).is_empty());
+        assert_eq!(contract.get_by_prefix("app".to_string(), "cache/1".to_string(), 0).len(), 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can do this")]
+    fn test_update_config_by_contract_account() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(bob(), None);
+        contract.update_config(Config::default());
+    }
+
+    #[test]
+    fn test_pause() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
+        contract.pause();
+        let status = contract.get("chat".to_string(), r#"{"ChannelStatus": {"channel_id": "general"}}"#.to_string());
+        assert!(status.is_some());
+        contract.unpause();
+        contract.post_message("chat".to_string(), chat_message("general", "we're back"));
+        assert_eq!(contract.get_channel("general".to_string()).messages.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is paused")]
+    fn test_post_while_paused() {
+        let context = get_context(vec![0, 1, 2]);
+        testing_env!(context);
+        let mut contract = MetanearChat::new(alice(), None);
+        contract.pause();
+        contract.post_message("chat".to_string(), chat_message("general", "hello"));
     }
 }